@@ -0,0 +1,208 @@
+use crate::build::Command;
+use crate::exec::{Pattern, PatternItem};
+use crate::observer::ExecutionObserver;
+use crate::types::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Iteration(u32),       // the next iteration is this one
+    DnaPrefix(Vec<Base>), // the DNA starts with these bases
+    Search(Vec<Base>),    // the pattern searches for exactly these bases
+    Rna(Command),         // the command is emitted
+    DnaLength(usize),     // the DNA length goes from one side of this to the other
+}
+
+impl Condition {
+    // Accepts what `Display` produces, e.g. `iteration 10` or `rna +RED`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (kind, arg) = s.trim().split_once(' ')?;
+        let arg = arg.trim();
+        let bases = || to_base_vec(arg.as_bytes()).ok().filter(|b| !b.is_empty());
+        match kind {
+            // Conditions are checked after an iteration, so 0 is never next
+            "iteration" => arg
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .map(Condition::Iteration),
+            "prefix" => bases().map(Condition::DnaPrefix),
+            "search" => bases().map(Condition::Search),
+            "rna" => Command::parse(arg).map(Condition::Rna),
+            "length" => arg.parse().ok().map(Condition::DnaLength),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bases = |b: &[Base]| b.iter().map(|b| b.to_string()).collect::<String>();
+        match self {
+            Condition::Iteration(n) => write!(f, "iteration {}", n),
+            Condition::DnaPrefix(b) => write!(f, "prefix {}", bases(b)),
+            Condition::Search(b) => write!(f, "search {}", bases(b)),
+            Condition::Rna(command) => write!(f, "rna {}", command),
+            Condition::DnaLength(n) => write!(f, "length {}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub condition: Condition,
+}
+
+// Watches the execution for the breakpoint conditions. Conditions are
+// checked once an iteration has finished, so execution stops between
+// iterations and the caller polls `take_hits` after every step.
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    dna_len_before: usize,
+    hits: Vec<usize>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, condition: Condition) -> usize {
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id: self.next_id,
+            condition,
+        });
+        self.next_id
+    }
+
+    pub fn delete(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // The breakpoints hit since the last call, in the order they were added
+    pub fn take_hits(&mut self) -> Vec<&Breakpoint> {
+        let hits = std::mem::take(&mut self.hits);
+        self.breakpoints
+            .iter()
+            .filter(|b| hits.contains(&b.id))
+            .collect()
+    }
+
+    fn hit_where(&mut self, mut f: impl FnMut(&Condition) -> bool) {
+        for b in &self.breakpoints {
+            if f(&b.condition) && !self.hits.contains(&b.id) {
+                self.hits.push(b.id);
+            }
+        }
+    }
+}
+
+impl ExecutionObserver for Breakpoints {
+    fn iteration_started(&mut self, _iteration: u32, dna: &DNA) {
+        self.dna_len_before = dna.len();
+    }
+
+    fn pattern_decoded(&mut self, pattern: &Pattern) {
+        self.hit_where(|c| match c {
+            Condition::Search(s) => pattern
+                .0
+                .iter()
+                .any(|p| matches!(p, PatternItem::Search(ps) if ps == s)),
+            _ => false,
+        });
+    }
+
+    fn rna_emitted(&mut self, rna: &[Base]) {
+        let emitted = Command::decode(rna);
+        self.hit_where(|c| *c == Condition::Rna(emitted.clone()));
+    }
+
+    fn iteration_finished(&mut self, iteration: u32, dna: &DNA, _rna: &[Base]) {
+        let before = self.dna_len_before;
+        self.hit_where(|c| match *c {
+            Condition::Iteration(n) => iteration.checked_add(1) == Some(n),
+            Condition::DnaPrefix(ref prefix) => {
+                prefix.len() <= dna.len()
+                    && prefix
+                        .iter()
+                        .enumerate()
+                        .all(|(i, &b)| dna.get(i) == Some(b))
+            }
+            Condition::DnaLength(threshold) => (before < threshold) != (dna.len() < threshold),
+            _ => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{example_dna, ExecutionState};
+
+    #[test]
+    fn test_condition_parse() {
+        for s in &[
+            "iteration 7",
+            "prefix ICFP",
+            "search IIC",
+            "rna +RED",
+            "length 100",
+        ] {
+            assert_eq!(Condition::parse(s).unwrap().to_string(), *s);
+        }
+        assert_eq!(Condition::parse("rna JUMP"), None);
+        assert_eq!(Condition::parse("prefix ICFX"), None);
+        assert_eq!(Condition::parse("iteration"), None);
+        assert_eq!(Condition::parse("iteration 0"), None);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let dna = example_dna();
+        let mut breakpoints = Breakpoints::new();
+        for s in &[
+            "iteration 1",
+            "prefix ICI",
+            "rna +RED",
+            "length 10",
+            "search ICP",
+        ] {
+            breakpoints.add(Condition::parse(s).unwrap());
+        }
+        assert!(breakpoints.delete(5));
+        assert!(!breakpoints.delete(5));
+        let mut state = ExecutionState::with_observer(b"", &dna, breakpoints).unwrap();
+        assert!(state.step());
+        let hits = state.observer_mut().take_hits();
+        assert_eq!(
+            hits.iter().map(|b| b.id).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert!(state.observer_mut().take_hits().is_empty());
+        assert!(state.step());
+        assert!(state.observer_mut().take_hits().is_empty());
+
+        let mut dna = to_u8_vec(&crate::dna::assemble("?\"IC\" ->").unwrap());
+        dna.extend(b"ICF");
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add(Condition::parse("search IC").unwrap());
+        breakpoints.add(Condition::parse("search I").unwrap());
+        let mut state = ExecutionState::with_observer(b"", &dna, breakpoints).unwrap();
+        assert!(state.step());
+        let hits = state.observer_mut().take_hits();
+        assert_eq!(hits.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+    }
+}
@@ -1,6 +1,8 @@
 use std::cell::Cell;
-use std::collections::{HashSet, VecDeque};
+use std::path::Path;
 
+use crate::error::{Error, Result};
+use crate::observer::{BuilderObserver, LayerSnapshots};
 use crate::types::*;
 
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
@@ -13,14 +15,15 @@ impl std::fmt::Display for Position {
 }
 
 impl Position {
-    pub fn move_(&self, dir: Direction) -> Self {
+    // Moves by one pixel, wrapping around the edges of a `width`x`height` canvas
+    pub fn move_(&self, dir: Direction, width: u32, height: u32) -> Self {
         let Position(x, y) = *self;
         use Direction::*;
         match dir {
-            Up => Position(x, y.wrapping_sub(1) % 600),
-            Right => Position(x.wrapping_add(1) % 600, y),
-            Down => Position(x, y.wrapping_add(1) % 600),
-            Left => Position(x.wrapping_sub(1) % 600, y),
+            Up => Position(x, (y + height - 1) % height),
+            Right => Position((x + 1) % width, y),
+            Down => Position(x, (y + 1) % height),
+            Left => Position((x + width - 1) % width, y),
         }
     }
 }
@@ -34,30 +37,49 @@ pub struct Pixel {
     pub a: u8,
 }
 
+pub const DEFAULT_WIDTH: u32 = 600;
+pub const DEFAULT_HEIGHT: u32 = 600;
+
 #[derive(Clone)]
 pub struct Bitmap {
+    width: u32,
+    height: u32,
     data: Vec<Pixel>,
 }
 
 impl Bitmap {
     pub fn transparent() -> Self {
+        Self::transparent_with_size(DEFAULT_WIDTH, DEFAULT_HEIGHT)
+    }
+
+    pub fn transparent_with_size(width: u32, height: u32) -> Self {
         Bitmap {
+            width,
+            height,
             data: vec![
                 Pixel {
                     rgb: BLACK,
                     a: TRANSPARENT,
                 };
-                600 * 600
+                (width * height) as usize
             ],
         }
     }
 
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
     pub fn get(&self, Position(x, y): Position) -> Pixel {
-        self.data[(y * 600 + x) as usize]
+        self.data[(y * self.width + x) as usize]
     }
 
     pub fn set(&mut self, Position(x, y): Position, pixel: Pixel) {
-        self.data[(y * 600 + x) as usize] = pixel;
+        self.data[(y * self.width + x) as usize] = pixel;
     }
 
     pub fn draw_line(
@@ -83,51 +105,60 @@ impl Bitmap {
         self.set(Position(x1, y1), pixel);
     }
 
-    pub fn fill(&mut self, Position(x, y): Position, new: Pixel) {
-        let old = self.get(Position(x, y));
-        if old != new {
-            let mut queue = VecDeque::new();
-            let mut visited = HashSet::new();
-            queue.push_back(Position(x, y));
-            visited.insert(Position(x, y));
-            while let Some(p) = queue.pop_front() {
-                self.set(p, new);
-                if x > 0 {
-                    let left = p.move_(Direction::Left);
-                    if !visited.contains(&left) && self.get(left) == old {
-                        queue.push_back(left);
-                        visited.insert(left);
-                    }
-                }
-                if x < 599 {
-                    let right = p.move_(Direction::Right);
-                    if !visited.contains(&right) && self.get(right) == old {
-                        queue.push_back(right);
-                        visited.insert(right);
-                    }
-                }
-                if y > 0 {
-                    let up = p.move_(Direction::Up);
-                    if !visited.contains(&up) && self.get(up) == old {
-                        queue.push_back(up);
-                        visited.insert(up);
-                    }
-                }
-                if y < 599 {
-                    let down = p.move_(Direction::Down);
-                    if !visited.contains(&down) && self.get(down) == old {
-                        queue.push_back(down);
-                        visited.insert(down);
+    pub fn fill(&mut self, start: Position, new: Pixel) {
+        let old = self.get(start);
+        if old == new {
+            return;
+        }
+        // Every filled pixel stops matching `old`, so no visited set is needed
+        let mut seeds = vec![start];
+        while let Some(Position(x, y)) = seeds.pop() {
+            if self.get(Position(x, y)) != old {
+                continue;
+            }
+            let mut left = x;
+            while left > 0 && self.get(Position(left - 1, y)) == old {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < self.width && self.get(Position(right + 1, y)) == old {
+                right += 1;
+            }
+            for x in left..=right {
+                self.set(Position(x, y), new);
+            }
+            // Seed each run of matching pixels in the neighbouring rows once
+            let above = y.checked_sub(1);
+            let below = Some(y + 1).filter(|&y| y < self.height);
+            for y in above.into_iter().chain(below) {
+                let mut in_run = false;
+                for x in left..=right {
+                    let matches = self.get(Position(x, y)) == old;
+                    if matches && !in_run {
+                        seeds.push(Position(x, y));
                     }
+                    in_run = matches;
                 }
-                visited.insert(Position(x, y));
             }
         }
     }
 
+    // Layers are combined pixel by pixel, so they must have the same size
+    fn assert_same_size(&self, other: &Self) {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "cannot combine a {}x{} layer with a {}x{} one",
+            self.width,
+            self.height,
+            other.width,
+            other.height
+        );
+    }
+
     pub fn compose_with(&mut self, other: Self) {
-        for y in 0..600u32 {
-            for x in 0..600u32 {
+        self.assert_same_size(&other);
+        for y in 0..self.height {
+            for x in 0..self.width {
                 let p0 = other.get(Position(x, y));
                 let r0 = p0.rgb.0 as u32;
                 let g0 = p0.rgb.1 as u32;
@@ -154,8 +185,9 @@ impl Bitmap {
     }
 
     pub fn clip_with(&mut self, other: Self) {
-        for y in 0..600u32 {
-            for x in 0..600u32 {
+        self.assert_same_size(&other);
+        for y in 0..self.height {
+            for x in 0..self.width {
                 let p0 = other.get(Position(x, y));
                 let a0 = p0.a as u32;
                 let p1 = self.get(Position(x, y));
@@ -207,6 +239,10 @@ impl Bucket {
         *self.current.get_mut() = None;
     }
 
+    pub fn len(&self) -> usize {
+        self.bucket.len()
+    }
+
     pub fn current_pixel(&self) -> Pixel {
         if let Some(pixel) = self.current.get() {
             return pixel;
@@ -320,241 +356,261 @@ pub enum Command {
     Unknown(Vec<Base>),
 }
 
-pub fn build(rna: &[u8]) -> Bitmap {
-    let mut builder = BuilderState::new(&to_base_vec(rna));
+const COMMAND_TABLE: [(Command, [Base; 7], &str); 20] = [
+    (Command::AddBlack, [P, I, P, I, I, I, C], "+BLACK"),
+    (Command::AddRed, [P, I, P, I, I, I, P], "+RED"),
+    (Command::AddGreen, [P, I, P, I, I, C, C], "+GREEN"),
+    (Command::AddYellow, [P, I, P, I, I, C, F], "+YELLOW"),
+    (Command::AddBlue, [P, I, P, I, I, C, P], "+BLUE"),
+    (Command::AddMagenta, [P, I, P, I, I, F, C], "+MAGENTA"),
+    (Command::AddCyan, [P, I, P, I, I, F, F], "+CYAN"),
+    (Command::AddWhite, [P, I, P, I, I, P, C], "+WHITE"),
+    (
+        Command::AddTransparent,
+        [P, I, P, I, I, P, F],
+        "+TRANSPARENT",
+    ),
+    (Command::AddOpaque, [P, I, P, I, I, P, P], "+OPAQUE"),
+    (Command::ClearBucket, [P, I, I, P, I, C, P], "CLEAR"),
+    (Command::Move, [P, I, I, I, I, I, P], "MOVE"),
+    (Command::TurnCcw, [P, C, C, C, C, C, P], "CCW"),
+    (Command::TurnCw, [P, F, F, F, F, F, P], "CW"),
+    (Command::Mark, [P, C, C, I, F, F, P], "MARK"),
+    (Command::DrawLine, [P, F, F, I, C, C, P], "LINE"),
+    (Command::Fill, [P, I, I, P, I, I, P], "FILL"),
+    (Command::AddLayer, [P, C, C, P, F, F, P], "LAYER"),
+    (Command::Compose, [P, F, F, P, C, C, P], "COMPOSE"),
+    (Command::Clip, [P, F, F, I, C, C, F], "CLIP"),
+];
+
+impl Command {
+    pub fn decode(rna: &[Base]) -> Self {
+        match COMMAND_TABLE.iter().find(|(_, code, _)| code[..] == *rna) {
+            Some((command, _, _)) => command.clone(),
+            None => Command::Unknown(rna.to_vec()),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<Base> {
+        match self {
+            Command::Unknown(b) => b.clone(),
+            _ => COMMAND_TABLE
+                .iter()
+                .find(|(command, _, _)| command == self)
+                .map(|(_, code, _)| code.to_vec())
+                .unwrap(),
+        }
+    }
+
+    // Accepts what `Display` produces: a mnemonic or `UNKNOWN` with the 7
+    // bases of a command
+    pub fn parse(s: &str) -> Option<Self> {
+        let parts = s.split_ascii_whitespace().collect::<Vec<_>>();
+        match parts[..] {
+            ["UNKNOWN", bases] => to_base_vec(bases.as_bytes())
+                .ok()
+                .filter(|b| b.len() == 7)
+                .map(Command::Unknown),
+            [mnemonic] => COMMAND_TABLE
+                .iter()
+                .find(|(_, _, m)| *m == mnemonic)
+                .map(|(command, _, _)| command.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Unknown(b) => {
+                write!(f, "UNKNOWN ")?;
+                for b in b {
+                    write!(f, "{}", b)?;
+                }
+                Ok(())
+            }
+            _ => {
+                let (_, _, mnemonic) = COMMAND_TABLE
+                    .iter()
+                    .find(|(command, _, _)| command == self)
+                    .unwrap();
+                write!(f, "{}", mnemonic)
+            }
+        }
+    }
+}
+
+pub fn build(rna: &[u8], layer_snapshots: Option<&Path>) -> Result<Bitmap> {
+    let observer = LayerSnapshots::new(layer_snapshots);
+    let mut builder = BuilderState::with_observer(&parse_bases(rna)?, observer)?;
     for _ in 0..builder.commands.len() {
         builder.step();
     }
-    builder.bitmaps.pop().unwrap()
+    builder.observer_mut().finish()?;
+    Ok(builder.bitmaps.pop().unwrap())
 }
 
-pub struct BuilderState {
+pub struct BuilderState<O: BuilderObserver = ()> {
     bucket: Bucket,
     pos: Position,
     mark: Position,
     dir: Direction,
+    width: u32,
+    height: u32,
     pub bitmaps: Vec<Bitmap>,
     pub commands: Vec<Command>,
     pub iteration: u32,
-    pub enable_debug_prints: bool,
+    observer: O,
 }
 
 impl BuilderState {
-    pub fn new(rna: &[Base]) -> Self {
-        BuilderState {
+    pub fn new(rna: &[Base]) -> Result<Self> {
+        Self::with_observer(rna, ())
+    }
+}
+
+impl<O: BuilderObserver> BuilderState<O> {
+    pub fn with_observer(rna: &[Base], observer: O) -> Result<Self> {
+        Self::with_size(rna, DEFAULT_WIDTH, DEFAULT_HEIGHT, observer)
+    }
+
+    pub fn with_size(rna: &[Base], width: u32, height: u32, observer: O) -> Result<Self> {
+        if !rna.len().is_multiple_of(7) {
+            return Err(Error::TruncatedRNA { length: rna.len() });
+        }
+        Ok(BuilderState {
             bucket: Bucket::new(),
             pos: Position(0, 0),
             mark: Position(0, 0),
             dir: Direction::Right,
-            bitmaps: vec![Bitmap::transparent()],
+            width,
+            height,
+            bitmaps: vec![Bitmap::transparent_with_size(width, height)],
             commands: Self::convert_rna_to_commands(rna),
             iteration: 0,
-            enable_debug_prints: false,
-        }
+            observer,
+        })
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    pub fn pos(&self) -> Position {
+        self.pos
+    }
+
+    pub fn mark(&self) -> Position {
+        self.mark
+    }
+
+    pub fn dir(&self) -> Direction {
+        self.dir
+    }
+
+    pub fn bucket_len(&self) -> usize {
+        self.bucket.len()
+    }
+
+    pub fn current_pixel(&self) -> Pixel {
+        self.bucket.current_pixel()
     }
 
     pub fn extend(&mut self, rna: &[Base]) {
         self.commands.extend(Self::convert_rna_to_commands(rna));
     }
 
-    fn convert_rna_to_commands(mut rna: &[Base]) -> Vec<Command> {
+    pub fn convert_rna_to_commands(mut rna: &[Base]) -> Vec<Command> {
         let mut commands = vec![];
         while rna.len() >= 7 {
-            commands.push(match &rna[0..7] {
-                &[P, I, P, I, I, I, C] => Command::AddBlack,
-                &[P, I, P, I, I, I, P] => Command::AddRed,
-                &[P, I, P, I, I, C, C] => Command::AddGreen,
-                &[P, I, P, I, I, C, F] => Command::AddYellow,
-                &[P, I, P, I, I, C, P] => Command::AddBlue,
-                &[P, I, P, I, I, F, C] => Command::AddMagenta,
-                &[P, I, P, I, I, F, F] => Command::AddCyan,
-                &[P, I, P, I, I, P, C] => Command::AddWhite,
-                &[P, I, P, I, I, P, F] => Command::AddTransparent,
-                &[P, I, P, I, I, P, P] => Command::AddOpaque,
-                &[P, I, I, P, I, C, P] => Command::ClearBucket,
-                &[P, I, I, I, I, I, P] => Command::Move,
-                &[P, C, C, C, C, C, P] => Command::TurnCcw,
-                &[P, F, F, F, F, F, P] => Command::TurnCw,
-                &[P, C, C, I, F, F, P] => Command::Mark,
-                &[P, F, F, I, C, C, P] => Command::DrawLine,
-                &[P, I, I, P, I, I, P] => Command::Fill,
-                &[P, C, C, P, F, F, P] => Command::AddLayer,
-                &[P, F, F, P, C, C, P] => Command::Compose,
-                &[P, F, F, I, C, C, F] => Command::Clip,
-                b => Command::Unknown(b.iter().cloned().collect()),
-            });
+            commands.push(Command::decode(&rna[0..7]));
             rna = &rna[7..];
         }
         commands
     }
 
     pub fn step(&mut self) -> &Bitmap {
-        if self.enable_debug_prints {
-            println!("Step {}", self.iteration);
-        }
-        match &self.commands[self.iteration as usize] {
-            Command::AddBlack => {
-                if self.enable_debug_prints {
-                    println!("+BLACK");
-                }
-                self.bucket.add_color(Color::RGB(BLACK));
-            }
-            Command::AddRed => {
-                if self.enable_debug_prints {
-                    println!("+RED");
-                }
-                self.bucket.add_color(Color::RGB(RED));
-            }
-            Command::AddGreen => {
-                if self.enable_debug_prints {
-                    println!("+GREEN");
-                }
-                self.bucket.add_color(Color::RGB(GREEN));
-            }
-            Command::AddYellow => {
-                if self.enable_debug_prints {
-                    println!("+YELLOW");
-                }
-                self.bucket.add_color(Color::RGB(YELLOW));
-            }
-            Command::AddBlue => {
-                if self.enable_debug_prints {
-                    println!("+BLUE");
-                }
-                self.bucket.add_color(Color::RGB(BLUE));
-            }
-            Command::AddMagenta => {
-                if self.enable_debug_prints {
-                    println!("+MAGENTA");
-                }
-                self.bucket.add_color(Color::RGB(MAGENTA));
-            }
-            Command::AddCyan => {
-                if self.enable_debug_prints {
-                    println!("+CYAN");
-                }
-                self.bucket.add_color(Color::RGB(CYAN));
-            }
-            Command::AddWhite => {
-                if self.enable_debug_prints {
-                    println!("+WHITE");
-                }
-                self.bucket.add_color(Color::RGB(WHITE));
-            }
-            Command::AddTransparent => {
-                if self.enable_debug_prints {
-                    println!("+TRANSPARENT");
-                }
-                self.bucket.add_color(Color::Transparency(TRANSPARENT));
-            }
-            Command::AddOpaque => {
-                if self.enable_debug_prints {
-                    println!("+OPAQUE");
-                }
-                self.bucket.add_color(Color::Transparency(OPAQUE));
-            }
-            Command::ClearBucket => {
-                if self.enable_debug_prints {
-                    println!("BUCKET CLEAR");
-                }
-                self.bucket.clear();
-            }
+        let command = &self.commands[self.iteration as usize];
+        self.observer.command_started(self.iteration, command);
+        let bucket_len = self.bucket.len();
+        match command {
+            Command::AddBlack => self.bucket.add_color(Color::RGB(BLACK)),
+            Command::AddRed => self.bucket.add_color(Color::RGB(RED)),
+            Command::AddGreen => self.bucket.add_color(Color::RGB(GREEN)),
+            Command::AddYellow => self.bucket.add_color(Color::RGB(YELLOW)),
+            Command::AddBlue => self.bucket.add_color(Color::RGB(BLUE)),
+            Command::AddMagenta => self.bucket.add_color(Color::RGB(MAGENTA)),
+            Command::AddCyan => self.bucket.add_color(Color::RGB(CYAN)),
+            Command::AddWhite => self.bucket.add_color(Color::RGB(WHITE)),
+            Command::AddTransparent => self.bucket.add_color(Color::Transparency(TRANSPARENT)),
+            Command::AddOpaque => self.bucket.add_color(Color::Transparency(OPAQUE)),
+            Command::ClearBucket => self.bucket.clear(),
             Command::Move => {
-                if self.enable_debug_prints {
-                    print!("pos: {} -> ", self.pos);
-                }
-                self.pos = self.pos.move_(self.dir);
-                if self.enable_debug_prints {
-                    println!("{}", self.pos);
-                }
+                let from = self.pos;
+                self.pos = self.pos.move_(self.dir, self.width, self.height);
+                self.observer.moved(from, self.pos);
             }
             Command::TurnCcw => {
-                if self.enable_debug_prints {
-                    print!("dir: {:?} -> ", self.dir);
-                }
+                let from = self.dir;
                 self.dir = self.dir.turn_ccw();
-                if self.enable_debug_prints {
-                    println!("{:?}", self.dir);
-                }
+                self.observer.turned(from, self.dir);
             }
             Command::TurnCw => {
-                if self.enable_debug_prints {
-                    print!("dir: {:?} -> ", self.dir);
-                }
+                let from = self.dir;
                 self.dir = self.dir.turn_cw();
-                if self.enable_debug_prints {
-                    println!("{:?}", self.dir);
-                }
+                self.observer.turned(from, self.dir);
             }
             Command::Mark => {
-                if self.enable_debug_prints {
-                    println!("mark := {}", self.pos);
-                }
                 self.mark = self.pos;
+                self.observer.mark_set(self.mark);
             }
             Command::DrawLine => {
-                if self.enable_debug_prints {
-                    println!("line: {} -> {}", self.pos, self.mark);
-                }
                 let idx = self.bitmaps.len() - 1;
-                self.bitmaps[idx].draw_line(self.pos, self.mark, self.bucket.current_pixel());
+                let pixel = self.bucket.current_pixel();
+                self.bitmaps[idx].draw_line(self.pos, self.mark, pixel);
+                self.observer.line_drawn(self.pos, self.mark, pixel);
             }
             Command::Fill => {
-                if self.enable_debug_prints {
-                    println!("fill: {}", self.pos);
-                }
                 let idx = self.bitmaps.len() - 1;
-                self.bitmaps[idx].fill(self.pos, self.bucket.current_pixel());
+                let pixel = self.bucket.current_pixel();
+                self.bitmaps[idx].fill(self.pos, pixel);
+                self.observer.fill_performed(self.pos, pixel);
             }
             Command::AddLayer => {
                 if self.bitmaps.len() < 10 {
-                    crate::png_utils::write_bitmap_as_png_rgba(
-                        self.bitmaps.last().unwrap(),
-                        std::fs::File::create(format!("./{}.png", self.iteration)).unwrap(),
-                    )
-                    .unwrap();
-                    if self.enable_debug_prints {
-                        println!("LAYER+");
-                    }
-                    self.bitmaps.push(Bitmap::transparent());
+                    self.bitmaps
+                        .push(Bitmap::transparent_with_size(self.width, self.height));
+                    self.observer.layer_pushed(self.iteration, &self.bitmaps);
                 }
             }
             Command::Compose => {
-                if self.enable_debug_prints {
-                    println!("LAYER COMPOSE");
-                }
                 if self.bitmaps.len() > 1 {
                     let bitmap = self.bitmaps.pop().unwrap();
                     let idx = self.bitmaps.len() - 1;
                     self.bitmaps[idx].compose_with(bitmap);
-                    crate::png_utils::write_bitmap_as_png_rgba(
-                        self.bitmaps.last().unwrap(),
-                        std::fs::File::create(format!("./{}.png", self.iteration)).unwrap(),
-                    )
-                    .unwrap();
+                    self.observer.layer_composed(self.iteration, &self.bitmaps);
                 }
             }
             Command::Clip => {
-                if self.enable_debug_prints {
-                    println!("LAYER CLIP");
-                }
                 if self.bitmaps.len() > 1 {
                     let bitmap = self.bitmaps.pop().unwrap();
                     let idx = self.bitmaps.len() - 1;
                     self.bitmaps[idx].clip_with(bitmap);
-                    crate::png_utils::write_bitmap_as_png_rgba(
-                        self.bitmaps.last().unwrap(),
-                        std::fs::File::create(format!("./{}.png", self.iteration)).unwrap(),
-                    )
-                    .unwrap();
-                }
-            }
-            Command::Unknown(b) => {
-                if self.enable_debug_prints {
-                    println!("UNKNOWN: {:?}", b);
+                    self.observer.layer_clipped(self.iteration, &self.bitmaps);
                 }
             }
+            Command::Unknown(_) => {}
+        }
+        if self.bucket.len() != bucket_len {
+            self.observer.bucket_changed(self.bucket.len());
         }
+        self.observer
+            .command_executed(self.iteration, command, &self.bitmaps);
         self.iteration += 1;
         self.bitmaps.last().unwrap()
     }
@@ -562,16 +618,16 @@ impl BuilderState {
     pub fn draw_debug_overlay(&self, bitmap: &mut Bitmap) {
         let mut pos = self.pos;
         let mut pixel = Pixel { rgb: RED, a: 128 };
-        bitmap.set(pos.move_(Direction::Up), pixel);
-        bitmap.set(pos.move_(Direction::Right), pixel);
-        bitmap.set(pos.move_(Direction::Down), pixel);
-        bitmap.set(pos.move_(Direction::Left), pixel);
+        bitmap.set(pos.move_(Direction::Up, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Right, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Down, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Left, self.width, self.height), pixel);
         pos = self.mark;
         pixel = Pixel { rgb: GREEN, a: 128 };
-        bitmap.set(pos.move_(Direction::Up), pixel);
-        bitmap.set(pos.move_(Direction::Right), pixel);
-        bitmap.set(pos.move_(Direction::Down), pixel);
-        bitmap.set(pos.move_(Direction::Left), pixel);
+        bitmap.set(pos.move_(Direction::Up, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Right, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Down, self.width, self.height), pixel);
+        bitmap.set(pos.move_(Direction::Left, self.width, self.height), pixel);
     }
 }
 
@@ -579,6 +635,13 @@ impl BuilderState {
 mod tests {
     use super::*;
 
+    #[test]
+    #[should_panic(expected = "cannot combine a 2x2 layer with a 1x2 one")]
+    fn test_compose_different_sizes() {
+        let mut bitmap = Bitmap::transparent_with_size(2, 2);
+        bitmap.compose_with(Bitmap::transparent_with_size(1, 2));
+    }
+
     #[test]
     fn test_bucket() {
         let b = Color::RGB(BLACK);
@@ -644,4 +707,71 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_move_wraps_around() {
+        use Direction::*;
+        assert_eq!(Position(0, 0).move_(Left, 4, 3), Position(3, 0));
+        assert_eq!(Position(0, 0).move_(Up, 4, 3), Position(0, 2));
+        assert_eq!(Position(3, 2).move_(Right, 4, 3), Position(0, 2));
+        assert_eq!(Position(3, 2).move_(Down, 4, 3), Position(3, 0));
+    }
+
+    #[test]
+    fn test_draw_on_small_bitmap() {
+        let p = Pixel {
+            rgb: RED,
+            a: OPAQUE,
+        };
+        let mut bitmap = Bitmap::transparent_with_size(4, 3);
+        bitmap.draw_line(Position(0, 0), Position(3, 2), p);
+        let drawn = (0..3)
+            .flat_map(|y| (0..4).map(move |x| Position(x, y)))
+            .filter(|&pos| bitmap.get(pos) == p)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            drawn,
+            vec![
+                Position(0, 0),
+                Position(1, 1),
+                Position(2, 1),
+                Position(3, 2)
+            ]
+        );
+
+        let mut bitmap = Bitmap::transparent_with_size(4, 3);
+        bitmap.fill(Position(1, 1), p);
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(bitmap.get(Position(x, y)), p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_stays_in_bounds() {
+        let wall = Pixel {
+            rgb: WHITE,
+            a: OPAQUE,
+        };
+        let p = Pixel {
+            rgb: RED,
+            a: OPAQUE,
+        };
+        let mut bitmap = Bitmap::transparent_with_size(5, 4);
+        bitmap.draw_line(Position(1, 0), Position(1, 3), wall);
+        bitmap.set(Position(3, 1), wall);
+        bitmap.set(Position(3, 2), wall);
+        let empty = bitmap.get(Position(0, 0));
+        bitmap.fill(Position(2, 1), p);
+        for y in 0..4 {
+            assert_eq!(bitmap.get(Position(0, y)), empty);
+            assert_eq!(bitmap.get(Position(1, y)), wall);
+            assert_eq!(bitmap.get(Position(2, y)), p);
+            assert_eq!(bitmap.get(Position(4, y)), p);
+        }
+        assert_eq!(bitmap.get(Position(3, 0)), p);
+        assert_eq!(bitmap.get(Position(3, 1)), wall);
+        assert_eq!(bitmap.get(Position(3, 3)), p);
+    }
 }
@@ -0,0 +1,229 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::observer::{DeferredError, ExecutionObserver};
+use crate::packed;
+use crate::types::*;
+
+// A snapshot of the execution between two iterations. `iteration` is the
+// number of the next iteration to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub iteration: u32,
+    pub dna: Vec<Base>,
+    pub rna: Vec<Base>,
+}
+
+// The file is the magic, the format version and the iteration as
+// little-endian u32s, then the DNA and the RNA. Each of them is a packed file
+// preceded by its size in bytes as a little-endian u64.
+pub const MAGIC: &[u8; 4] = b"\0CHK";
+pub const VERSION: u32 = 1;
+
+impl Checkpoint {
+    pub fn encode(&self) -> Vec<u8> {
+        let dna = packed::encode(&self.dna);
+        let rna = packed::encode(&self.rna);
+        let mut result = Vec::with_capacity(28 + dna.len() + rna.len());
+        result.extend(MAGIC);
+        result.extend(&VERSION.to_le_bytes());
+        result.extend(&self.iteration.to_le_bytes());
+        for section in &[dna, rna] {
+            result.extend(&(section.len() as u64).to_le_bytes());
+            result.extend(section);
+        }
+        result
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidCheckpoint {
+                message: "not a checkpoint file",
+            });
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(Error::CheckpointVersion { version });
+        }
+        let iteration = reader.u32()?;
+        let dna = reader.section()?;
+        let rna = reader.section()?;
+        // The builder only takes whole commands
+        if !rna.len().is_multiple_of(7) {
+            return Err(Error::InvalidCheckpoint {
+                message: "the RNA ends in the middle of a command",
+            });
+        }
+        if !reader.data.is_empty() {
+            return Err(Error::InvalidCheckpoint {
+                message: "trailing data after the RNA",
+            });
+        }
+        Ok(Checkpoint {
+            iteration,
+            dna,
+            rna,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    // Writes next to the destination first so that an interrupted save
+    // never leaves a truncated checkpoint behind
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, self.encode())?;
+        fs::rename(&temp, path)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(Error::InvalidCheckpoint {
+                message: "the file is truncated",
+            });
+        }
+        let (result, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(result)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn section(&mut self) -> Result<Vec<Base>> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        let size = usize::try_from(u64::from_le_bytes(bytes)).unwrap_or(usize::MAX);
+        packed::decode(self.take(size)?)
+    }
+}
+
+// Saves a checkpoint after every `every` iterations
+pub struct CheckpointWriter {
+    every: u32,
+    path: PathBuf,
+    error: DeferredError,
+}
+
+impl CheckpointWriter {
+    pub fn new(every: u32, path: &Path) -> Self {
+        assert!(every > 0, "checkpoints need a positive interval");
+        CheckpointWriter {
+            every,
+            path: path.to_path_buf(),
+            error: DeferredError::default(),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.error.take()
+    }
+}
+
+impl ExecutionObserver for CheckpointWriter {
+    fn iteration_finished(&mut self, iteration: u32, dna: &DNA, rna: &[Base]) {
+        let done = match iteration.checked_add(1) {
+            Some(done) if done.is_multiple_of(self.every) => done,
+            _ => return,
+        };
+        let path = &self.path;
+        self.error.run(|| {
+            let checkpoint = Checkpoint {
+                iteration: done,
+                dna: dna.render(&dna.slice(0..dna.len())),
+                rna: rna.to_vec(),
+            };
+            checkpoint.save(path)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{example_dna, ExecutionState};
+
+    #[test]
+    fn test_checkpoint_format() {
+        let checkpoint = Checkpoint {
+            iteration: 42,
+            dna: to_base_vec(b"ICFPPFCIICF").unwrap(),
+            rna: to_base_vec(b"PIPIIIC").unwrap(),
+        };
+        let data = checkpoint.encode();
+        assert_eq!(Checkpoint::decode(&data).unwrap(), checkpoint);
+        assert!(matches!(
+            Checkpoint::decode(&data[..data.len() - 1]),
+            Err(Error::InvalidCheckpoint { .. })
+        ));
+        assert!(matches!(
+            Checkpoint::decode(b"ICFP"),
+            Err(Error::InvalidCheckpoint { .. })
+        ));
+        let partial = Checkpoint {
+            rna: to_base_vec(b"PIP").unwrap(),
+            ..checkpoint.clone()
+        };
+        assert!(matches!(
+            Checkpoint::decode(&partial.encode()),
+            Err(Error::InvalidCheckpoint { .. })
+        ));
+        let mut newer = data.clone();
+        newer[4] = 2;
+        assert!(matches!(
+            Checkpoint::decode(&newer),
+            Err(Error::CheckpointVersion { version: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_writer() {
+        let path =
+            std::env::temp_dir().join(format!("icfpc2007-{}.checkpoint", std::process::id()));
+        let dna = DNA::new(&[to_base_vec(b"ICFP").unwrap()]);
+        let mut writer = CheckpointWriter::new(2, &path);
+        writer.iteration_finished(0, &dna, &[]);
+        assert!(!path.exists());
+        writer.iteration_finished(1, &dna, &[]);
+        // The iteration after the last one has no number
+        writer.iteration_finished(u32::MAX, &dna, &[]);
+        writer.finish().unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.iteration, 2);
+        assert_eq!(checkpoint.dna, to_base_vec(b"ICFP").unwrap());
+    }
+
+    #[test]
+    fn test_resume() {
+        let dna = example_dna();
+        let mut state = ExecutionState::new(b"", &dna).unwrap();
+        assert!(state.step());
+        let checkpoint = state.checkpoint();
+        assert_eq!(checkpoint.iteration, 1);
+        assert_eq!(checkpoint.dna, to_base_vec(b"ICIICIICF").unwrap());
+        let mut resumed =
+            ExecutionState::from_checkpoint(Checkpoint::decode(&checkpoint.encode()).unwrap(), ());
+        assert_eq!(resumed.iteration(), 1);
+        while state.step() {}
+        while resumed.step() {}
+        assert_eq!(resumed.rna, state.rna);
+        assert_eq!(resumed.checkpoint(), state.checkpoint());
+    }
+}
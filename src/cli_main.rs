@@ -1,26 +1,195 @@
 use std::io::{self, BufRead, Write};
+use std::path::Path;
 
-use crate::build::BuilderState;
-use crate::exec::ExecutionState;
-use crate::png_utils::*;
+use icfpc2007::breakpoint::{Breakpoints, Condition};
+use icfpc2007::build::{BuilderState, Command};
+use icfpc2007::checkpoint::Checkpoint;
+use icfpc2007::dna;
+use icfpc2007::error::{Error, Result};
+use icfpc2007::exec::ExecutionState;
+use icfpc2007::observer::{BuilderDebugPrinter, DebugPrinter, LastMatch, SlowIterationReporter};
+use icfpc2007::png_utils::*;
+use icfpc2007::types::{parse_prefix, Base};
 
 enum Mode {
     DNA,
     RNA,
 }
 
-pub fn cli_main(prefix: &[u8], dna: &[u8]) -> io::Result<()> {
-    let mut exec_state = ExecutionState::new(prefix, dna);
-    let mut build_state: BuilderState = BuilderState::new(&[]);
+const BREAK_USAGE: &str = "Usage: break [iteration <n> | prefix <bases> | search <bases> | \
+                           rna <command> | length <n>]";
+
+type CliObserver = (SlowIterationReporter, DebugPrinter, Breakpoints, LastMatch);
+type CliExecutionState = ExecutionState<CliObserver>;
+
+// How many bases `x` prints per line and `env` shows per group
+const BASES_PER_LINE: usize = 64;
+// How many commands `rna` shows without a count
+const DEFAULT_RNA_COUNT: usize = 10;
+
+pub fn cli_main(prefix: &[u8], dna: &[u8]) -> Result<()> {
+    let exec_state = ExecutionState::with_observer(prefix, dna, observer())?;
+    let prefix_len = parse_prefix(prefix)?.len();
     println!(
         "DNA loaded: {} bases, {} base prefix",
-        dna.len(),
-        prefix.len()
+        exec_state.dna().len() - prefix_len,
+        prefix_len
+    );
+    run(exec_state)
+}
+
+pub fn cli_resume(checkpoint: Checkpoint) -> Result<()> {
+    let exec_state = ExecutionState::from_checkpoint(checkpoint, observer());
+    println!(
+        "Resumed at iteration {}: {} bases of DNA, {} RNA commands",
+        exec_state.iteration(),
+        exec_state.dna().len(),
+        exec_state.rna.len() / 7
     );
+    run(exec_state)
+}
+
+fn observer() -> CliObserver {
+    (
+        SlowIterationReporter::default(),
+        DebugPrinter::default(),
+        Breakpoints::new(),
+        LastMatch::new(BASES_PER_LINE),
+    )
+}
+
+fn bases_to_string(bases: &[Base]) -> String {
+    bases.iter().map(|b| b.to_string()).collect()
+}
+
+fn print_dna(exec_state: &CliExecutionState, offset: usize, len: usize) {
+    let dna = exec_state.dna();
+    if offset > dna.len() {
+        println!(
+            "{}",
+            Error::OutOfBounds {
+                index: offset,
+                length: dna.len()
+            }
+        );
+        return;
+    }
+    let end = offset.saturating_add(len).min(dna.len());
+    let bases = dna.render(&dna.slice(offset..end));
+    for (i, line) in bases.chunks(BASES_PER_LINE).enumerate() {
+        println!(
+            "{:>8}  {}",
+            offset + i * BASES_PER_LINE,
+            bases_to_string(line)
+        );
+    }
+}
+
+fn print_env(last_match: &LastMatch) {
+    let (iteration, length) = match last_match.matched {
+        Some(matched) => matched,
+        None => {
+            println!("No match yet");
+            return;
+        }
+    };
+    println!(
+        "Iteration {} matched {} bases, {} groups",
+        iteration,
+        length,
+        last_match.groups.len()
+    );
+    for (i, (len, bases)) in last_match.groups.iter().enumerate() {
+        let more = if *len > bases.len() { "..." } else { "" };
+        println!(
+            "  \\{} ({} bases): {}{}",
+            i,
+            len,
+            bases_to_string(bases),
+            more
+        );
+    }
+}
+
+fn print_rna(rna: &[Base], count: usize) {
+    let total = rna.len() / 7;
+    let first = total.saturating_sub(count);
+    for (i, command) in rna[first * 7..total * 7].chunks(7).enumerate() {
+        println!("{:>8}  {}", (first + i) * 7, Command::decode(command));
+    }
+}
+
+fn print_builder_state(build_state: &BuilderState<BuilderDebugPrinter>) {
+    let (pos, mark) = (build_state.pos(), build_state.mark());
+    let pixel = build_state.current_pixel();
+    println!("Position: ({}, {})", pos.0, pos.1);
+    println!("Mark: ({}, {})", mark.0, mark.1);
+    println!("Direction: {:?}", build_state.dir());
+    println!(
+        "Bucket: {} colors, pixel RGB({}, {}, {}) alpha {}",
+        build_state.bucket_len(),
+        pixel.rgb.0,
+        pixel.rgb.1,
+        pixel.rgb.2,
+        pixel.a
+    );
+    println!("Layers: {}", build_state.bitmaps.len());
+    println!(
+        "Commands: {} of {} executed",
+        build_state.iteration,
+        build_state.commands.len()
+    );
+}
+
+// Runs one iteration, returns false once the DNA is finished or a
+// breakpoint is hit
+fn step_dna(exec_state: &mut CliExecutionState, dna_processed: &mut bool) -> bool {
+    *dna_processed = !exec_state.step();
+    if let Err(e) = exec_state.check_finish() {
+        println!("{}", e);
+    }
+    let iteration = exec_state.iteration();
+    let hits = exec_state.observer_mut().2.take_hits();
+    for b in &hits {
+        println!(
+            "Breakpoint {} hit before iteration {}: {}",
+            b.id, iteration, b.condition
+        );
+    }
+    !*dna_processed && hits.is_empty()
+}
+
+// Hands the RNA generated since the last call over to the builder, returns
+// whether there was any
+fn take_new_rna(
+    exec_state: &CliExecutionState,
+    build_state: &mut BuilderState<BuilderDebugPrinter>,
+    last_rna_count: &mut usize,
+) -> bool {
+    if exec_state.rna.len() == *last_rna_count {
+        return false;
+    }
+    build_state.extend(&exec_state.rna[*last_rna_count..]);
+    println!(
+        "New RNA generated: {} commands",
+        (exec_state.rna.len() - *last_rna_count) / 7
+    );
+    *last_rna_count = exec_state.rna.len();
+    true
+}
+
+fn run(mut exec_state: CliExecutionState) -> Result<()> {
+    // RNA from before a checkpoint is built right away so that stepping
+    // continues from the same picture
+    let mut build_state =
+        BuilderState::with_observer(&exec_state.rna, BuilderDebugPrinter::default())?;
+    while (build_state.iteration as usize) < build_state.commands.len() {
+        build_state.step();
+    }
     let mut dna_processed = false;
     let mut last_command = String::new();
     let mut mode = Mode::DNA;
-    let mut last_rna_count = 0;
+    let mut last_rna_count = exec_state.rna.len();
     loop {
         match mode {
             Mode::DNA => print!("dna> "),
@@ -43,20 +212,16 @@ pub fn cli_main(prefix: &[u8], dna: &[u8]) -> io::Result<()> {
                 match mode {
                     Mode::DNA => {
                         if !dna_processed {
-                            exec_state.enable_debug_prints = false;
-                            for _ in 0..num_steps - 1 {
-                                exec_state.step();
+                            exec_state.observer_mut().1.enabled = false;
+                            for i in 1..=num_steps {
+                                exec_state.observer_mut().1.enabled = i == num_steps;
+                                if !step_dna(&mut exec_state, &mut dna_processed) {
+                                    break;
+                                }
                             }
-                            exec_state.enable_debug_prints = true;
-                            dna_processed = !exec_state.step();
+                            exec_state.observer_mut().1.enabled = true;
                         }
-                        if exec_state.rna.len() > last_rna_count {
-                            build_state.extend(&exec_state.rna[last_rna_count..]);
-                            println!(
-                                "New RNA generated: {} commands",
-                                (exec_state.rna.len() - last_rna_count) / 7
-                            );
-                            last_rna_count = exec_state.rna.len();
+                        if take_new_rna(&exec_state, &mut build_state, &mut last_rna_count) {
                             mode = Mode::RNA;
                         }
                     }
@@ -64,11 +229,11 @@ pub fn cli_main(prefix: &[u8], dna: &[u8]) -> io::Result<()> {
                         if (build_state.iteration as usize) < build_state.commands.len() {
                             num_steps = num_steps
                                 .min(build_state.commands.len() as u32 - build_state.iteration);
-                            build_state.enable_debug_prints = false;
+                            build_state.observer_mut().enabled = false;
                             for _ in 0..num_steps - 1 {
                                 build_state.step();
                             }
-                            build_state.enable_debug_prints = true;
+                            build_state.observer_mut().enabled = true;
                             let mut bitmap = build_state.step().clone();
                             build_state.draw_debug_overlay(&mut bitmap);
                             write_bitmap_as_png_rgba(
@@ -86,28 +251,24 @@ pub fn cli_main(prefix: &[u8], dna: &[u8]) -> io::Result<()> {
             "until" | "u" => match mode {
                 Mode::DNA => {
                     if !dna_processed {
-                        exec_state.enable_debug_prints = true;
-                        while exec_state.rna.len() == last_rna_count && !dna_processed {
-                            dna_processed = !exec_state.step();
+                        exec_state.observer_mut().1.enabled = true;
+                        while exec_state.rna.len() == last_rna_count {
+                            if !step_dna(&mut exec_state, &mut dna_processed) {
+                                break;
+                            }
                         }
                     }
-                    if exec_state.rna.len() > last_rna_count {
-                        build_state.extend(&exec_state.rna[last_rna_count..]);
-                        println!(
-                            "New RNA generated: {} commands",
-                            (exec_state.rna.len() - last_rna_count) / 7
-                        );
-                        last_rna_count = exec_state.rna.len();
+                    if take_new_rna(&exec_state, &mut build_state, &mut last_rna_count) {
                         mode = Mode::RNA;
                     }
                 }
                 Mode::RNA => {
                     let num_steps = build_state.commands.len() as u32 - build_state.iteration;
-                    build_state.enable_debug_prints = false;
+                    build_state.observer_mut().enabled = false;
                     for _ in 0..num_steps - 1 {
                         build_state.step();
                     }
-                    build_state.enable_debug_prints = true;
+                    build_state.observer_mut().enabled = true;
                     let mut bitmap = build_state.step().clone();
                     build_state.draw_debug_overlay(&mut bitmap);
                     write_bitmap_as_png_rgba(
@@ -118,6 +279,90 @@ pub fn cli_main(prefix: &[u8], dna: &[u8]) -> io::Result<()> {
                     mode = Mode::DNA;
                 }
             },
+            "continue" | "c" => match mode {
+                Mode::DNA => {
+                    if !dna_processed {
+                        exec_state.observer_mut().1.enabled = false;
+                        while step_dna(&mut exec_state, &mut dna_processed) {
+                            // Do nothing
+                        }
+                        exec_state.observer_mut().1.enabled = true;
+                    }
+                    if dna_processed {
+                        println!("DNA finished at iteration {}", exec_state.iteration());
+                    }
+                    if take_new_rna(&exec_state, &mut build_state, &mut last_rna_count) {
+                        mode = Mode::RNA;
+                    }
+                }
+                Mode::RNA => {
+                    println!("Breakpoints are for DNA, finish the RNA first");
+                    continue;
+                }
+            },
+            "break" | "b" => {
+                let breakpoints = &mut exec_state.observer_mut().2;
+                if parts.len() == 1 {
+                    for b in breakpoints.breakpoints() {
+                        println!("Breakpoint {}: {}", b.id, b.condition);
+                    }
+                    continue;
+                }
+                match Condition::parse(&parts[1..].join(" ")) {
+                    Some(condition) => {
+                        let id = breakpoints.add(condition.clone());
+                        println!("Breakpoint {}: {}", id, condition);
+                    }
+                    None => {
+                        println!("{}", BREAK_USAGE);
+                        continue;
+                    }
+                }
+            }
+            "delete" => {
+                let breakpoints = &mut exec_state.observer_mut().2;
+                match parts.get(1).map(|id| id.parse()) {
+                    None => breakpoints.clear(),
+                    Some(Ok(id)) if breakpoints.delete(id) => {}
+                    Some(_) => println!("No breakpoint {}", parts[1]),
+                }
+                continue;
+            }
+            "x" => {
+                let numbers = parts[1..]
+                    .iter()
+                    .map(|n| n.parse::<usize>())
+                    .collect::<std::result::Result<Vec<_>, _>>();
+                match numbers.as_deref() {
+                    Ok([offset, len]) => print_dna(&exec_state, *offset, *len),
+                    _ => {
+                        println!("Usage: x <offset> <len>");
+                        continue;
+                    }
+                }
+            }
+            "peek" => print!("{}", dna::disassemble(&exec_state, 0, Some(1))),
+            "env" => print_env(&exec_state.observer().3),
+            "rna" => match parts.get(1).map(|n| n.parse()) {
+                None => print_rna(&exec_state.rna, DEFAULT_RNA_COUNT),
+                Some(Ok(count)) => print_rna(&exec_state.rna, count),
+                Some(Err(_)) => {
+                    println!("Usage: rna [n]");
+                    continue;
+                }
+            },
+            "state" => print_builder_state(&build_state),
+            "stats" => exec_state.dna().debug_print(),
+            "save" => {
+                if parts.len() < 2 {
+                    println!("Usage: save <file>");
+                    continue;
+                }
+                match exec_state.checkpoint().save(Path::new(parts[1])) {
+                    Ok(()) => println!("Checkpoint saved at iteration {}", exec_state.iteration()),
+                    Err(e) => println!("Cannot save {}: {}", parts[1], e),
+                }
+            }
             "dump" | "d" => {
                 for (i, b) in build_state.bitmaps.iter().enumerate() {
                     write_bitmap_as_png_rgba(
@@ -0,0 +1,339 @@
+use std::fmt::Write;
+
+use crate::build::Command;
+use crate::error::{Error, Result};
+use crate::exec::{self, ExecutionState, Pattern, PatternItem, Template, TemplateItem};
+use crate::observer::ExecutionObserver;
+use crate::types::*;
+
+// Compiles the textual instruction notation into DNA. Every line is one of:
+//
+//   <pattern> -> <template>   using the `Display` syntax of `Pattern` and `Template`
+//   rna <command>             emits an RNA command, as bases or as a mnemonic
+//
+// Whitespace is insignificant, `#` starts a comment and an optional leading
+// offset (as printed by `disassemble`) is ignored.
+pub fn assemble(text: &str) -> Result<Vec<Base>> {
+    let mut result = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let syntax_error = |message: String| Error::Syntax {
+            line: i + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap().trim();
+        let line = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(command) = line.strip_prefix("rna ") {
+            let command = command.trim();
+            let rna = match to_base_vec(command.as_bytes()) {
+                Ok(rna) => rna,
+                Err(_) => Command::parse(command)
+                    .ok_or_else(|| syntax_error(format!("unknown RNA command: {}", command)))?
+                    .encode(),
+            };
+            if rna.len() != 7 {
+                return Err(syntax_error(format!(
+                    "RNA commands are 7 bases long, got {}",
+                    rna.len()
+                )));
+            }
+            result.extend(&[I, I, I]);
+            result.extend(rna);
+            continue;
+        }
+        let (pattern, template) = match line.find("->") {
+            Some(idx) => (&line[..idx], &line[idx + 2..]),
+            None => {
+                return Err(syntax_error(
+                    "expected `<pattern> -> <template>`".to_string(),
+                ))
+            }
+        };
+        let pattern = parse_pattern(pattern).map_err(syntax_error)?;
+        let template = parse_template(template).map_err(syntax_error)?;
+        result.extend(encode_pattern(&pattern).map_err(syntax_error)?);
+        result.extend(encode_template(&template));
+    }
+    Ok(result)
+}
+
+// Lists the instructions of the DNA starting `start` bases in without running
+// them, in the notation `assemble` reads. Every line carries the base offset
+// of its instruction. RNA emitted from within a pattern or template can't be
+// assembled back, so it is listed as a comment after its instruction. Only
+// pattern and template pairs count toward `limit`, so the RNA emitted before
+// the last of them is listed too.
+pub fn disassemble<O: ExecutionObserver>(
+    state: &ExecutionState<O>,
+    start: usize,
+    limit: Option<usize>,
+) -> String {
+    let mut state = state.unobserved();
+    let start = start.min(state.dna().len());
+    state.dna_mut().truncate_front(start);
+    let total_len = start + state.dna().len();
+    let mut result = String::new();
+    let mut count = 0;
+    let stop_reason = loop {
+        let offset = total_len - state.dna().len();
+        if state.dna().is_empty() {
+            break "end of DNA".to_string();
+        }
+        if limit == Some(count) {
+            break "instruction limit reached".to_string();
+        }
+        if state.dna().len() >= 10 && (0..3).all(|i| state.dna().get(i) == Some(I)) {
+            let rna = state.dna().render(&state.dna().slice(3..10));
+            state.dna_mut().truncate_front(10);
+            writeln!(result, "{:>8}  rna {}", offset, format_rna(&rna)).unwrap();
+            continue;
+        }
+        count += 1;
+        let rna_len = state.rna.len();
+        let instruction = state.pattern().and_then(|p| Ok((p, state.template()?)));
+        match instruction {
+            Ok((pattern, template)) => {
+                writeln!(result, "{:>8}  {} -> {}", offset, pattern, template).unwrap()
+            }
+            Err(e) => break format!("incomplete instruction, {}", e),
+        }
+        for rna in state.rna[rna_len..].chunks(7) {
+            writeln!(result, "{:>8}  # rna {}", "", format_rna(rna)).unwrap();
+        }
+    };
+    let offset = total_len - state.dna().len();
+    writeln!(result, "# stopped at {}: {}", offset, stop_reason).unwrap();
+    result
+}
+
+fn format_rna(rna: &[Base]) -> String {
+    match Command::decode(rna) {
+        Command::Unknown(bases) => bases.iter().map(|b| b.to_string()).collect(),
+        command => command.to_string(),
+    }
+}
+
+pub fn parse_pattern(s: &str) -> std::result::Result<Pattern, String> {
+    let mut parser = Parser::new(s);
+    let mut items = vec![];
+    let mut level = 0;
+    while let Some(c) = parser.next() {
+        items.push(match c {
+            '!' => PatternItem::Skip(parser.number()?),
+            '?' => PatternItem::Search(parser.quoted_bases()?),
+            '(' => {
+                level += 1;
+                PatternItem::GroupOpen
+            }
+            ')' => {
+                if level == 0 {
+                    return Err("unbalanced `)` in pattern".to_string());
+                }
+                level -= 1;
+                PatternItem::GroupClose
+            }
+            _ => PatternItem::Base(Parser::base(c)?),
+        });
+    }
+    if level != 0 {
+        return Err("unclosed `(` in pattern".to_string());
+    }
+    Ok(Pattern(items))
+}
+
+pub fn parse_template(s: &str) -> std::result::Result<Template, String> {
+    let mut parser = Parser::new(s);
+    let mut items = vec![];
+    while let Some(c) = parser.next() {
+        items.push(match c {
+            '\\' => {
+                let n = parser.number()?;
+                parser.expect(':')?;
+                TemplateItem::Ref(n, parser.number()?)
+            }
+            '~' => TemplateItem::Length(parser.number()?),
+            _ => TemplateItem::Base(Parser::base(c)?),
+        });
+    }
+    Ok(Template(items))
+}
+
+pub fn encode_pattern(pattern: &Pattern) -> std::result::Result<Vec<Base>, String> {
+    let mut result = vec![];
+    let mut after_search = false;
+    for item in &pattern.0 {
+        match item {
+            PatternItem::Base(b) => {
+                // The decoder would read the base as a part of the search constant
+                if after_search {
+                    return Err(format!("base {} cannot follow a search constant", b));
+                }
+                result.extend(exec::quote(&[*b]));
+            }
+            PatternItem::Skip(n) => {
+                result.extend(&[I, P]);
+                result.extend(exec::as_nat(*n));
+            }
+            PatternItem::Search(s) => {
+                result.extend(&[I, F, F]);
+                result.extend(exec::quote(s));
+            }
+            PatternItem::GroupOpen => result.extend(&[I, I, P]),
+            PatternItem::GroupClose => result.extend(&[I, I, C]),
+        }
+        after_search = matches!(item, PatternItem::Search(_));
+    }
+    result.extend(&[I, I, C]);
+    Ok(result)
+}
+
+pub fn encode_template(template: &Template) -> Vec<Base> {
+    let mut result = vec![];
+    for item in &template.0 {
+        match item {
+            TemplateItem::Base(b) => result.extend(exec::quote(&[*b])),
+            TemplateItem::Ref(n, l) => {
+                result.extend(&[I, F]);
+                result.extend(exec::as_nat(*l));
+                result.extend(exec::as_nat(*n));
+            }
+            TemplateItem::Length(n) => {
+                result.extend(&[I, I, P]);
+                result.extend(exec::as_nat(*n));
+            }
+        }
+    }
+    result.extend(&[I, I, C]);
+    result
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Parser {
+            chars: s.chars().peekable(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.next()
+    }
+
+    fn expect(&mut self, expected: char) -> std::result::Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected `{}`, got `{}`", expected, c)),
+            None => Err(format!("expected `{}`", expected)),
+        }
+    }
+
+    fn number(&mut self) -> std::result::Result<usize, String> {
+        self.skip_whitespace();
+        let mut digits = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        digits
+            .parse()
+            .map_err(|_| format!("expected a number, got `{}`", digits))
+    }
+
+    fn quoted_bases(&mut self) -> std::result::Result<Vec<Base>, String> {
+        self.expect('"')?;
+        let mut result = vec![];
+        loop {
+            match self.next() {
+                Some('"') => break Ok(result),
+                Some(c) => result.push(Self::base(c)?),
+                None => break Err("unterminated search constant".to_string()),
+            }
+        }
+    }
+
+    fn base(c: char) -> std::result::Result<Base, String> {
+        match c {
+            'I' => Ok(I),
+            'C' => Ok(C),
+            'F' => Ok(F),
+            'P' => Ok(P),
+            _ => Err(format!("unexpected `{}`", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble() {
+        assert_eq!(
+            assemble("(!2)P -> \\0:0").unwrap(),
+            to_base_vec(b"IIPIPICPIICICIICIFPPIIC").unwrap()
+        );
+        assert_eq!(
+            assemble("# find and drop\n?\"FF\" ->\n").unwrap(),
+            to_base_vec(b"IFFPPIICIIC").unwrap()
+        );
+        assert_eq!(
+            assemble("rna +RED\n   12  P -> ~1\n").unwrap(),
+            to_base_vec(b"IIIPIPIIIPICIICIIPCPIIC").unwrap()
+        );
+        assert!(matches!(
+            assemble("P -> P\n?\"I\"I -> P"),
+            Err(Error::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            assemble("(P -> P"),
+            Err(Error::Syntax { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let source = "rna +RED\n(!2)P -> \\0:0\n?\"FF\" -> \nrna CCCCCCC\n";
+        let dna = to_u8_vec(&assemble(source).unwrap());
+        // Cut the last RNA command short
+        let state = ExecutionState::new(b"", &dna[..dna.len() - 2]).unwrap();
+        assert_eq!(
+            disassemble(&state, 0, None),
+            "       0  rna +RED
+      10  (!2)P -> \\0:0
+      33  ?\"FF\" -> 
+# stopped at 52: incomplete instruction, DNA ends in the middle of a pattern
+"
+        );
+        assert_eq!(
+            disassemble(&state, 0, Some(1)),
+            "       0  rna +RED
+      10  (!2)P -> \\0:0
+# stopped at 33: instruction limit reached
+"
+        );
+        assert_eq!(
+            disassemble(&state, 10, Some(1)),
+            "      10  (!2)P -> \\0:0
+# stopped at 33: instruction limit reached
+"
+        );
+    }
+
+    #[test]
+    fn test_assemble_roundtrip() {
+        let source = "(!2)?\"ICFP\"(P) -> \\1:2~0I";
+        let mut state = ExecutionState::new(b"", &to_u8_vec(&assemble(source).unwrap())).unwrap();
+        let pattern = state.pattern().unwrap();
+        let template = state.template().unwrap();
+        assert_eq!(format!("{} -> {}", pattern, template), source);
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidBase { offset: usize, byte: u8 },
+    TruncatedRNA { length: usize },
+    TruncatedPacked { length: usize },
+    OutOfBounds { index: usize, length: usize },
+    Syntax { line: usize, message: String },
+    InvalidCheckpoint { message: &'static str },
+    CheckpointVersion { version: u32 },
+    ProtectionLimit { limit: usize },
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidBase { offset, byte } => write!(
+                f,
+                "invalid base '{}' at offset {}",
+                byte.escape_ascii(),
+                offset
+            ),
+            Error::TruncatedRNA { length } => write!(
+                f,
+                "RNA length {} is not a multiple of 7, the last command is incomplete",
+                length
+            ),
+            Error::TruncatedPacked { length } => {
+                write!(f, "packed bases of {} bytes are truncated", length)
+            }
+            Error::OutOfBounds { index, length } => {
+                write!(f, "index {} is out of bounds for length {}", index, length)
+            }
+            Error::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            Error::InvalidCheckpoint { message } => write!(f, "invalid checkpoint: {}", message),
+            Error::CheckpointVersion { version } => write!(
+                f,
+                "checkpoint version {} is not supported, expected {}",
+                version,
+                crate::checkpoint::VERSION
+            ),
+            Error::ProtectionLimit { limit } => write!(
+                f,
+                "execution stopped before the DNA finished: a protected reference is longer \
+                 than the limit of {} bases",
+                limit
+            ),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -1,25 +1,29 @@
 use std::result::Result;
-use std::time::Instant;
 
+use crate::checkpoint::Checkpoint;
+use crate::error;
+use crate::observer::ExecutionObserver;
 use crate::types::*;
 
-pub struct ExecutionState {
+#[derive(Clone)]
+pub struct ExecutionState<O: ExecutionObserver = ()> {
     dna: DNA,
     pub rna: Vec<Base>,
     iteration: u32,
-    pub enable_debug_prints: bool,
+    observer: O,
+    finish: Option<EarlyFinish>,
 }
 
-#[derive(Debug, PartialEq)]
-enum PatternItem {
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternItem {
     Base(Base),
-    Skip(u32),
+    Skip(usize),
     Search(Vec<Base>),
     GroupOpen,
     GroupClose,
 }
-#[derive(Debug, PartialEq)]
-struct Pattern(Vec<PatternItem>);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern(pub Vec<PatternItem>);
 
 impl std::fmt::Display for Pattern {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -44,13 +48,13 @@ impl std::fmt::Display for Pattern {
 }
 
 #[derive(Debug, PartialEq)]
-enum TemplateItem {
+pub enum TemplateItem {
     Base(Base),
-    Ref(u32, u32),
-    Length(u32),
+    Ref(usize, usize),
+    Length(usize),
 }
 #[derive(Debug, PartialEq)]
-struct Template(Vec<TemplateItem>);
+pub struct Template(pub Vec<TemplateItem>);
 
 impl std::fmt::Display for Template {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -66,96 +70,197 @@ impl std::fmt::Display for Template {
     }
 }
 
-pub fn execute(prefix: &[u8], dna: &[u8]) -> Vec<Base> {
-    let mut state = ExecutionState::new(prefix, dna);
+pub fn execute(prefix: &[u8], dna: &[u8]) -> error::Result<Vec<Base>> {
+    let (rna, _) = execute_with(prefix, dna, ())?;
+    Ok(rna)
+}
+
+// Runs the DNA to the end and gives the observer back along with the RNA
+pub fn execute_with<O: ExecutionObserver>(
+    prefix: &[u8],
+    dna: &[u8],
+    observer: O,
+) -> error::Result<(Vec<Base>, O)> {
+    let mut state = ExecutionState::with_observer(prefix, dna, observer)?;
     while state.step() {
         // Do nothing
     }
-    return state.rna;
+    state.check_finish()?;
+    Ok((state.rna, state.observer))
 }
 
-#[derive(Debug, PartialEq)]
-struct EarlyFinish;
+// Tells why an instruction could not be finished: the DNA ran out in the
+// pattern or the template, or a protected reference grew too long
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum EarlyFinish {
+    Pattern,
+    Template,
+    Protection,
+}
 type CanFinishEarly<T> = Result<T, EarlyFinish>;
 
+impl std::fmt::Display for EarlyFinish {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EarlyFinish::Pattern => write!(f, "DNA ends in the middle of a pattern"),
+            EarlyFinish::Template => write!(f, "DNA ends in the middle of a template"),
+            EarlyFinish::Protection => write!(
+                f,
+                "protected reference is longer than the limit of {} bases",
+                MAX_PROTECTED_LEN
+            ),
+        }
+    }
+}
+
 impl ExecutionState {
-    pub fn new(prefix: &[u8], dna_base: &[u8]) -> Self {
-        ExecutionState {
-            dna: DNA::new(&vec![to_base_vec(prefix), to_base_vec(dna_base)]),
+    pub fn new(prefix: &[u8], dna_base: &[u8]) -> error::Result<Self> {
+        Self::with_observer(prefix, dna_base, ())
+    }
+}
+
+impl<O: ExecutionObserver> ExecutionState<O> {
+    pub fn with_observer(prefix: &[u8], dna_base: &[u8], observer: O) -> error::Result<Self> {
+        Ok(ExecutionState {
+            dna: DNA::new(&[parse_prefix(prefix)?, parse_bases(dna_base)?]),
             rna: vec![],
             iteration: 0,
-            enable_debug_prints: false,
+            observer,
+            finish: None,
+        })
+    }
+
+    pub fn from_checkpoint(checkpoint: Checkpoint, observer: O) -> Self {
+        ExecutionState {
+            dna: DNA::new(&[checkpoint.dna]),
+            rna: checkpoint.rna,
+            iteration: checkpoint.iteration,
+            observer,
+            finish: None,
         }
     }
 
-    pub fn step(&mut self) -> bool {
-        if self.enable_debug_prints {
-            println!("iteration {}", self.iteration);
-            println!("dna length: {}", self.dna.len());
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            iteration: self.iteration,
+            dna: self.dna.render(&self.dna.slice(0..self.dna.len())),
+            rna: self.rna.clone(),
+        }
+    }
+
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    pub fn dna(&self) -> &DNA {
+        &self.dna
+    }
+
+    pub(crate) fn dna_mut(&mut self) -> &mut DNA {
+        &mut self.dna
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    // A copy of the DNA and RNA that nothing observes
+    pub(crate) fn unobserved(&self) -> ExecutionState {
+        ExecutionState {
+            dna: self.dna.clone(),
+            rna: self.rna.clone(),
+            iteration: self.iteration,
+            observer: (),
+            finish: self.finish,
+        }
+    }
+
+    // Running into `MAX_PROTECTED_LEN` is a limit of this implementation
+    // rather than the end of the DNA, so it is an error
+    pub fn check_finish(&self) -> error::Result<()> {
+        match self.finish {
+            Some(EarlyFinish::Protection) => Err(error::Error::ProtectionLimit {
+                limit: MAX_PROTECTED_LEN,
+            }),
+            _ => Ok(()),
         }
-        let time = Instant::now();
+    }
+
+    pub fn step(&mut self) -> bool {
+        self.observer.iteration_started(self.iteration, &self.dna);
         let pattern = match self.pattern() {
             Ok(p) => p,
-            Err(_) => return false,
+            Err(e) => return self.finished(e),
         };
+        self.observer.pattern_decoded(&pattern);
         let template = match self.template() {
             Ok(t) => t,
-            Err(_) => return false,
+            Err(e) => return self.finished(e),
         };
-        if self.enable_debug_prints {
-            println!("pattern: {}", pattern);
-            println!("template: {}", template);
-        }
-        self.match_replace(pattern, template);
-        if time.elapsed().as_millis() > 10 {
-            println!("SLOW ITERATION {}: {}ms", self.iteration, time.elapsed().as_millis());
-            self.dna.debug_print();
-        }
-        if self.enable_debug_prints {
-            println!("rna length: {} ({})", self.rna.len() / 7, self.rna.len());
-            println!();
+        self.observer.template_decoded(&template);
+        match self.match_pattern(&pattern) {
+            Some((length, env)) => {
+                self.observer.match_succeeded(length, &env, &self.dna);
+                let result = match self.replace(template, &env) {
+                    Ok(r) => r,
+                    Err(e) => return self.finished(e),
+                };
+                self.observer.replacement_built(&result);
+                self.dna.truncate_front(length);
+                self.dna.extend_front(result);
+            }
+            None => self.observer.match_failed(),
         }
+        self.observer
+            .iteration_finished(self.iteration, &self.dna, &self.rna);
         self.iteration += 1;
         true
     }
 
+    fn finished(&mut self, reason: EarlyFinish) -> bool {
+        self.finish = Some(reason);
+        false
+    }
+
+    #[cfg(test)]
     fn match_replace(&mut self, pattern: Pattern, template: Template) {
+        if let Some((length, env)) = self.match_pattern(&pattern) {
+            let result = self.replace(template, &env).unwrap();
+            self.dna.truncate_front(length);
+            self.dna.extend_front(result);
+        }
+    }
+
+    // Returns the length of the match and the environment
+    fn match_pattern(&self, pattern: &Pattern) -> Option<(usize, Vec<DNASlice>)> {
         let mut i = 0;
         let mut env = vec![];
         let mut c = vec![];
-        for p in pattern.0 {
+        for p in &pattern.0 {
             use PatternItem::*;
-            match p {
+            match *p {
                 Base(b) => {
-                    if self.dna[i] == b {
+                    if self.dna.get(i) == Some(b) {
                         i += 1;
                     } else {
-                        return;
+                        return None;
                     }
                 }
                 Skip(n) => {
-                    i += n as usize;
+                    // Saturated nats always run past the end
+                    i = i.saturating_add(n);
                     if i > self.dna.len() {
-                        return;
-                    }
-                }
-                Search(s) => {
-                    if s.len() > 0 {
-                        if i + s.len() > self.dna.len() {
-                            return;
-                        }
-                        let mut window = self.dna.window(i, s.len());
-                        loop {
-                            if window.is_match(&s) {
-                                i += window.offset() + s.len();
-                                break;
-                            }
-                            if !window.next() {
-                                return;
-                            }
-                        };
+                        return None;
                     }
                 }
+                Search(ref s) => match self.dna.find(s, i) {
+                    Some(pos) => i = pos + s.len(),
+                    None => return None,
+                },
                 GroupOpen => {
                     c.push(i);
                 }
@@ -166,24 +271,10 @@ impl ExecutionState {
                 }
             }
         }
-        if self.enable_debug_prints {
-            println!("match length: {}", i);
-        }
-        let result = self.replace(template, &env);
-        self.dna.truncate_front(i);
-        self.dna.extend_front(result);
+        Some((i, env))
     }
 
-    fn replace(&self, template: Template, env: &Vec<DNASlice>) -> Vec<DNAChunk> {
-        if self.enable_debug_prints {
-            for (i, p) in env.iter().enumerate() {
-                print!("env[{}] = ", i);
-                for b in self.dna.render(&p.slice(0..10.min(p.len()))) {
-                    print!("{:?}", b);
-                }
-                println!("{} ({})", if p.len() > 10 { "..." } else { "" }, p.len());
-            }
-        }
+    fn replace(&self, template: Template, env: &[DNASlice]) -> CanFinishEarly<Vec<DNAChunk>> {
         let mut result = vec![];
         let mut current_owned_chunk = vec![];
         for t in template.0 {
@@ -195,14 +286,13 @@ impl ExecutionState {
                         result.push(DNAChunk::Owned(current_owned_chunk));
                         current_owned_chunk = vec![];
                     }
-                    if (n as usize) < env.len() {
+                    // References past the environment are empty
+                    if let Some(e) = env.get(n) {
                         if l == 0 {
-                            result.push(DNAChunk::Slice(env[n as usize].clone()));
-                        } else {
-                            result.push(DNAChunk::Owned(Self::protect(
-                                l,
-                                self.dna.render(&env[n as usize]),
-                            )));
+                            result.push(DNAChunk::Slice(e.clone()));
+                        } else if !e.is_empty() {
+                            let protected = protect(l, self.dna.bases(e));
+                            result.push(DNAChunk::Owned(protected.ok_or(EarlyFinish::Protection)?));
                         }
                     }
                 }
@@ -211,37 +301,33 @@ impl ExecutionState {
                         result.push(DNAChunk::Owned(current_owned_chunk));
                         current_owned_chunk = vec![];
                     }
-                    let length = if (n as usize) < env.len() {
-                        env[n as usize].len()
-                    } else {
-                        0
-                    };
-                    result.push(DNAChunk::Owned(Self::as_nat(length as u32)));
+                    let length = env.get(n).map_or(0, DNASlice::len);
+                    result.push(DNAChunk::Owned(as_nat(length)));
                 }
             }
         }
         if !current_owned_chunk.is_empty() {
             result.push(DNAChunk::Owned(current_owned_chunk));
         }
-        result
+        Ok(result)
     }
 
-    fn pattern(&mut self) -> CanFinishEarly<Pattern> {
+    pub(crate) fn pattern(&mut self) -> CanFinishEarly<Pattern> {
         let mut pat = vec![];
         let mut level = 0;
         loop {
-            match self.dna.pop_front().ok_or(EarlyFinish)? {
+            match self.dna.pop_front().ok_or(EarlyFinish::Pattern)? {
                 C => pat.push(PatternItem::Base(I)),
                 F => pat.push(PatternItem::Base(C)),
                 P => pat.push(PatternItem::Base(F)),
-                I => match self.dna.pop_front().ok_or(EarlyFinish)? {
+                I => match self.dna.pop_front().ok_or(EarlyFinish::Pattern)? {
                     C => pat.push(PatternItem::Base(P)),
-                    P => pat.push(PatternItem::Skip(self.nat()?)),
+                    P => pat.push(PatternItem::Skip(self.nat().ok_or(EarlyFinish::Pattern)?)),
                     F => {
                         self.dna.pop_front(); // Skip an extra base
                         pat.push(PatternItem::Search(self.consts()));
                     }
-                    I => match self.dna.pop_front().ok_or(EarlyFinish)? {
+                    I => match self.dna.pop_front().ok_or(EarlyFinish::Pattern)? {
                         P => {
                             level += 1;
                             pat.push(PatternItem::GroupOpen);
@@ -254,8 +340,7 @@ impl ExecutionState {
                             pat.push(PatternItem::GroupClose);
                         }
                         I => {
-                            self.rna.extend(self.dna.render(&self.dna.slice(0..7)));
-                            self.dna.truncate_front(7);
+                            self.emit_rna();
                         }
                     },
                 },
@@ -263,25 +348,28 @@ impl ExecutionState {
         }
     }
 
-    fn template(&mut self) -> CanFinishEarly<Template> {
+    pub(crate) fn template(&mut self) -> CanFinishEarly<Template> {
         let mut result = vec![];
         loop {
-            match self.dna.pop_front().ok_or(EarlyFinish)? {
+            match self.dna.pop_front().ok_or(EarlyFinish::Template)? {
                 C => result.push(TemplateItem::Base(I)),
                 F => result.push(TemplateItem::Base(C)),
                 P => result.push(TemplateItem::Base(F)),
-                I => match self.dna.pop_front().ok_or(EarlyFinish)? {
+                I => match self.dna.pop_front().ok_or(EarlyFinish::Template)? {
                     C => result.push(TemplateItem::Base(P)),
                     F | P => {
-                        let l = self.nat()?;
-                        result.push(TemplateItem::Ref(self.nat()?, l));
+                        let l = self.nat().ok_or(EarlyFinish::Template)?;
+                        let n = self.nat().ok_or(EarlyFinish::Template)?;
+                        result.push(TemplateItem::Ref(n, l));
                     }
-                    I => match self.dna.pop_front().ok_or(EarlyFinish)? {
+                    I => match self.dna.pop_front().ok_or(EarlyFinish::Template)? {
                         C | F => break Ok(Template(result)),
-                        P => result.push(TemplateItem::Length(self.nat()?)),
+                        P => {
+                            let n = self.nat().ok_or(EarlyFinish::Template)?;
+                            result.push(TemplateItem::Length(n));
+                        }
                         I => {
-                            self.rna.extend(self.dna.render(&self.dna.slice(0..7)));
-                            self.dna.truncate_front(7);
+                            self.emit_rna();
                         }
                     },
                 },
@@ -289,97 +377,178 @@ impl ExecutionState {
         }
     }
 
-    fn nat(&mut self) -> CanFinishEarly<u32> {
-        let mut stack = vec![];
-        loop {
-            match self.dna.pop_front().ok_or(EarlyFinish)? {
-                P => {
-                    let mut result = 0;
-                    while let Some(x) = stack.pop() {
-                        result = result * 2 + x;
-                    }
-                    break Ok(result);
-                }
-                I | F => stack.push(0),
-                C => stack.push(1),
-            }
+    fn emit_rna(&mut self) {
+        // The DNA may end in the middle of an RNA command. The partial command
+        // is dropped so that the RNA only ever holds whole commands.
+        if self.dna.len() < 7 {
+            self.dna.truncate_front(self.dna.len());
+            return;
         }
+        let rna = self.dna.render(&self.dna.slice(0..7));
+        self.observer.rna_emitted(&rna);
+        self.rna.extend(rna);
+        self.dna.truncate_front(7);
     }
 
-    fn as_nat(mut n: u32) -> Vec<Base> {
-        let mut result = vec![];
-        while n > 0 {
-            if n % 2 == 0 {
-                result.push(I);
-            } else {
-                result.push(C);
+    // Nats too large for a usize saturate to usize::MAX, which is past the
+    // end of any DNA, so skips by them fail just like the spec's bignums would
+    fn nat(&mut self) -> Option<usize> {
+        let mut result: usize = 0;
+        let mut bit = 0;
+        loop {
+            match self.dna.pop_front()? {
+                P => break Some(result),
+                I | F => {}
+                C => {
+                    result = if bit < usize::BITS {
+                        result.saturating_add(1 << bit)
+                    } else {
+                        usize::MAX
+                    }
+                }
             }
-            n /= 2;
+            bit = bit.saturating_add(1);
         }
-        result.push(P);
-        result
     }
 
     fn consts(&mut self) -> Vec<Base> {
         let mut result = vec![];
         loop {
-            if self.dna.is_empty() {
-                break;
-            }
-            match self.dna[0] {
-                C => {
+            match self.dna.get(0) {
+                Some(C) => {
                     self.dna.pop_front();
                     result.push(I);
                 }
-                F => {
+                Some(F) => {
                     self.dna.pop_front();
                     result.push(C);
                 }
-                P => {
+                Some(P) => {
                     self.dna.pop_front();
                     result.push(F);
                 }
-                I => {
-                    if self.dna.is_empty() {
-                        break;
+                Some(I) => match self.dna.get(1) {
+                    Some(C) => {
+                        self.dna.pop_front();
+                        self.dna.pop_front();
+                        result.push(P);
                     }
-                    match self.dna[1] {
-                        C => {
-                            self.dna.pop_front();
-                            self.dna.pop_front();
-                            result.push(P);
-                        }
-                        _ => break,
-                    }
-                }
+                    _ => break,
+                },
+                None => break,
             }
         }
         result
     }
+}
 
-    fn protect<'a>(l: u32, data: Vec<Base>) -> Vec<Base> {
-        if l == 0 {
-            panic!("This method should only be called if quouting is required");
+pub(crate) fn as_nat(mut n: usize) -> Vec<Base> {
+    let mut result = vec![];
+    while n > 0 {
+        if n.is_multiple_of(2) {
+            result.push(I);
+        } else {
+            result.push(C);
         }
-        let mut result = data;
-        for _ in 0..l {
-            result = Self::quote(&result);
+        n /= 2;
+    }
+    result.push(P);
+    result
+}
+
+// Quoting never shrinks the data and grows it without bound, so protecting
+// by a huge level finishes the execution instead of exhausting the memory
+pub const MAX_PROTECTED_LEN: usize = 1 << 28;
+
+// Quotes `l` times in a single pass. The expansions of every base at level
+// l are built level by level from q(I) = C, q(C) = F, q(F) = P, q(P) = IC,
+// so q^l(I) = q^(l-1)(C) and so on, without copying the data l times.
+// Returns None if the result would be longer than `MAX_PROTECTED_LEN`.
+fn protect(l: usize, data: impl Iterator<Item = Base>) -> Option<Vec<Base>> {
+    if l == 0 {
+        panic!("This method should only be called if quouting is required");
+    }
+    let data = data.collect::<Vec<_>>();
+    if data.is_empty() {
+        return Some(data);
+    }
+    // Checks the length first, it reaches the limit after a few hundred levels
+    let mut counts = [0usize; 4];
+    for &b in &data {
+        counts[match b {
+            I => 0,
+            C => 1,
+            F => 2,
+            P => 3,
+        }] += 1;
+    }
+    let mut lengths = [1usize; 4];
+    for _ in 0..l {
+        let [i, c, f, p] = lengths;
+        lengths = [c, f, p, i.saturating_add(c)];
+        let total = (0..4).fold(0usize, |total, b| {
+            total.saturating_add(counts[b].saturating_mul(lengths[b]))
+        });
+        if total > MAX_PROTECTED_LEN {
+            return None;
         }
-        result
     }
+    // Only the expansions the data uses get built, needed[k] tells which
+    // ones are needed at level k. Both indexed by I, C, F, P.
+    let mut needed = vec![[false; 4]; l + 1];
+    for (b, &count) in counts.iter().enumerate() {
+        needed[l][b] = count > 0;
+    }
+    for k in (1..=l).rev() {
+        let [i, c, f, p] = needed[k];
+        needed[k - 1] = [p, i || p, c, f];
+    }
+    let mut expansions = [vec![I], vec![C], vec![F], vec![P]];
+    for level in &needed[1..] {
+        let [i, c, f, p] = expansions;
+        let ic = if level[3] {
+            let mut ic = i;
+            ic.extend(&c);
+            ic
+        } else {
+            vec![]
+        };
+        let keep = |needed: bool, e: Vec<Base>| if needed { e } else { vec![] };
+        expansions = [keep(level[0], c), keep(level[1], f), keep(level[2], p), ic];
+    }
+    let mut result = vec![];
+    for b in data {
+        let expansion = match b {
+            I => &expansions[0],
+            C => &expansions[1],
+            F => &expansions[2],
+            P => &expansions[3],
+        };
+        result.extend(expansion);
+    }
+    Some(result)
+}
 
-    fn quote(d: &Vec<Base>) -> Vec<Base> {
-        let mut result = vec![];
-        for b in d {
-            match b {
-                I => result.push(C),
-                C => result.push(F),
-                F => result.push(P),
-                P => result.extend(&[I, C]),
-            }
+pub(crate) fn quote(d: &[Base]) -> Vec<Base> {
+    let mut result = vec![];
+    for b in d {
+        match b {
+            I => result.push(C),
+            C => result.push(F),
+            F => result.push(P),
+            P => result.extend(&[I, C]),
         }
-        result
     }
+    result
+}
+
+// Emits +RED while decoding the pattern, replaces ICP with IC, then fails to
+// match P on F
+#[cfg(test)]
+pub(crate) fn example_dna() -> Vec<u8> {
+    let mut dna = to_u8_vec(&crate::dna::assemble("rna +RED\n(!2)P -> \\0:0").unwrap());
+    dna.extend(b"ICPIICIICF");
+    dna
 }
 
 #[cfg(test)]
@@ -390,15 +559,15 @@ mod tests {
     fn test_patterns() {
         use PatternItem::*;
         assert_eq!(
-            ExecutionState::new(b"", b"CIIC").pattern(),
+            ExecutionState::new(b"", b"CIIC").unwrap().pattern(),
             Ok(Pattern(vec![Base(I)]))
         );
         assert_eq!(
-            ExecutionState::new(b"", b"IIPIPICPIICICIIF").pattern(),
+            ExecutionState::new(b"", b"IIPIPICPIICICIIF").unwrap().pattern(),
             Ok(Pattern(vec![GroupOpen, Skip(2), GroupClose, Base(P)])),
         );
         assert_eq!(
-            ExecutionState::new(b"", b"IIPIPICPIICIFCCFPICIIF").pattern(),
+            ExecutionState::new(b"", b"IIPIPICPIICIFCCFPICIIF").unwrap().pattern(),
             Ok(Pattern(vec![
                 GroupOpen,
                 Skip(2),
@@ -410,35 +579,111 @@ mod tests {
 
     #[test]
     fn test_execute() {
-        let mut state = ExecutionState::new(b"", b"IIPIPICPIICICIIFICCIFPPIICCFPC");
+        let mut state = ExecutionState::new(b"", b"IIPIPICPIICICIIFICCIFPPIICCFPC").unwrap();
         let pattern = state.pattern().unwrap();
         let template = state.template().unwrap();
         state.match_replace(pattern, template);
         assert_eq!(
             state.dna.render(&state.dna.slice(0..state.dna.len())),
-            to_base_vec(b"PICFC")
+            to_base_vec(b"PICFC").unwrap()
         );
-        state = ExecutionState::new(b"", b"IIPIPICPIICICIIFICCIFCCCPPIICCFPC");
+        state = ExecutionState::new(b"", b"IIPIPICPIICICIIFICCIFCCCPPIICCFPC").unwrap();
         let pattern = state.pattern().unwrap();
         let template = state.template().unwrap();
         state.match_replace(pattern, template);
         assert_eq!(
             state.dna.render(&state.dna.slice(0..state.dna.len())),
-            to_base_vec(b"PIICCFCFFPC")
+            to_base_vec(b"PIICCFCFFPC").unwrap()
         );
-        state = ExecutionState::new(b"", b"IIPIPIICPIICIICCIICFCFC");
+        state = ExecutionState::new(b"", b"IIPIPIICPIICIICCIICFCFC").unwrap();
         let pattern = state.pattern().unwrap();
         let template = state.template().unwrap();
         state.match_replace(pattern, template);
         assert_eq!(
             state.dna.render(&state.dna.slice(0..state.dna.len())),
-            to_base_vec(b"I")
+            to_base_vec(b"I").unwrap()
         );
         // Find "FF" and replace with nothing
-        state = ExecutionState::new(b"", b"IFCPPIICIICPFF");
+        state = ExecutionState::new(b"", b"IFCPPIICIICPFF").unwrap();
         let pattern = state.pattern().unwrap();
         let template = state.template().unwrap();
         state.match_replace(pattern, template);
         assert_eq!(state.dna.len(), 0);
     }
+
+    #[test]
+    fn test_protect() {
+        for data in &[&b"ICFPPFCI"[..], b"I", b"FF", b"CP"] {
+            let data = to_base_vec(data).unwrap();
+            let mut quoted = data.clone();
+            for l in 1..12 {
+                quoted = quote(&quoted);
+                assert_eq!(protect(l, data.iter().cloned()), Some(quoted.clone()));
+            }
+        }
+        let data = to_base_vec(b"ICFPPFCI").unwrap();
+        assert_eq!(protect(usize::MAX, data.iter().cloned()), None);
+        assert_eq!(protect(usize::MAX, [].iter().cloned()), Some(vec![]));
+    }
+
+    #[test]
+    fn test_huge_nats() {
+        let huge = "C".repeat(100) + "P";
+        let mut state = ExecutionState::new(b"", huge.as_bytes()).unwrap();
+        assert_eq!(state.nat(), Some(usize::MAX));
+        assert_eq!(state.dna.len(), 0);
+        let mut state = ExecutionState::new(b"", b"ICICP").unwrap();
+        assert_eq!(state.nat(), Some(10));
+        assert_eq!(as_nat(10), to_base_vec(b"ICICP").unwrap());
+
+        // A skip by a saturated nat fails the match and leaves the DNA alone
+        let dna = format!("IP{}IICIICIIPCIIC", huge);
+        let mut state = ExecutionState::new(b"", dna.as_bytes()).unwrap();
+        assert!(state.step());
+        assert_eq!(state.dna.len(), 7);
+
+        // A huge reference is empty, the length of a huge reference is zero
+        let template = format!("CIFP{}IIP{}IIC", huge, huge);
+        let dna = format!("CIIC{}IF", template);
+        let mut state = ExecutionState::new(b"", dna.as_bytes()).unwrap();
+        assert!(state.step());
+        assert_eq!(
+            state.dna.render(&state.dna.slice(0..state.dna.len())),
+            to_base_vec(b"IPF").unwrap()
+        );
+
+        // A huge protection level finishes the execution with an error
+        let source = format!("(!1) -> \\0:{}", usize::MAX);
+        let mut dna = to_u8_vec(&crate::dna::assemble(&source).unwrap());
+        dna.extend(b"ICF");
+        let mut state = ExecutionState::new(b"", &dna).unwrap();
+        assert!(!state.step());
+        assert!(matches!(
+            state.check_finish(),
+            Err(error::Error::ProtectionLimit { .. })
+        ));
+        assert!(matches!(
+            execute(b"", &dna),
+            Err(error::Error::ProtectionLimit { .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_dna() {
+        // RNA emission cut short by the end of the DNA
+        let mut state = ExecutionState::new(b"", b"IIIPIP").unwrap();
+        assert!(!state.step());
+        assert!(state.rna.is_empty());
+        assert!(state.dna.is_empty());
+        // Search constant running up to the end of the DNA
+        let mut state = ExecutionState::new(b"", b"IFFCFI").unwrap();
+        assert_eq!(state.pattern(), Err(EarlyFinish::Pattern));
+        assert!(matches!(
+            ExecutionState::new(b"ICFP", b"IC\nFPX"),
+            Err(error::Error::InvalidBase {
+                offset: 5,
+                byte: b'X'
+            })
+        ));
+    }
 }
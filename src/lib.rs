@@ -0,0 +1,17 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod breakpoint;
+pub mod build;
+pub mod checkpoint;
+pub mod dna;
+pub mod error;
+pub mod exec;
+pub mod observer;
+pub mod packed;
+pub mod png_utils;
+pub mod profile;
+pub mod render;
+pub mod rna;
+pub mod score;
+pub mod trace;
+pub mod types;
@@ -1,44 +1,280 @@
-use std::{env, fs};
+#![allow(clippy::upper_case_acronyms)]
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::path::Path;
+use std::{env, fs, io, process};
+
+use icfpc2007::build::build;
+use icfpc2007::checkpoint::{Checkpoint, CheckpointWriter};
+use icfpc2007::dna;
+use icfpc2007::exec::{execute_with, ExecutionState};
+use icfpc2007::observer::SlowIterationReporter;
+use icfpc2007::packed;
+use icfpc2007::png_utils::{read_png_as_bitmap, write_bitmap_as_png};
+use icfpc2007::profile::Profiler;
+use icfpc2007::render::render_with;
+use icfpc2007::rna;
+use icfpc2007::score::{count_differing_pixels, risk};
+use icfpc2007::trace::TraceWriter;
+use icfpc2007::types::{parse_bases, parse_prefix, to_u8_vec, Base};
 
-mod build;
 mod cli_main;
-mod exec;
-mod png_utils;
-mod types;
 
-use build::build;
-use cli_main::cli_main;
-use exec::execute;
-use png_utils::write_bitmap_as_png;
-use types::to_u8_vec;
+use cli_main::{cli_main, cli_resume};
 
-fn usage() -> std::io::Result<()> {
+fn usage() {
     eprintln!("Usage:
-  <program> execute [in]DNA [out]RNA
-  <program> build [in]RNA [out]PNG");
-    Ok(())
+  <program> execute [--prefix <bases> | --prefix-file <path>] [--packed] [--trace <file>] [--profile]
+          [--checkpoint-every <iterations> [--checkpoint <file>]] [in]DNA [out]RNA
+  <program> build [--layer-snapshots <dir>] [in]RNA [out]PNG
+  <program> render [--prefix <bases> | --prefix-file <path>] [--layer-snapshots <dir>] [in]DNA [out]PNG
+  <program> score [--prefix <bases> | --prefix-file <path>] [in]PNG [in]TARGET
+  <program> rna disasm [in]RNA
+  <program> rna asm [--packed] [in]TXT [out]RNA
+  <program> dna asm [--packed] [in]SRC [out]PREFIX
+  <program> dna disasm [--prefix <bases> | --prefix-file <path>] [--start <offset>] [--limit <count>] [in]DNA
+  <program> cli [--prefix <bases> | --prefix-file <path>] [in]DNA
+  <program> cli --resume <file>
+  <program> convert [--packed] [in]BASES [out]BASES
+
+Files of bases are read in either the text or the packed format,
+--packed writes the packed one. Without --prefix or --prefix-file the
+prefix is {}, --prefix \"\" runs the DNA alone.
+Checkpoints go to RNA.checkpoint unless --checkpoint is given.", DEFAULT_PREFIX);
+}
+
+// The prefix from the task description
+const DEFAULT_PREFIX: &str = "IIPIFFCPICICIICPIICIPPPICIIC";
+
+const PROFILE_TOP_N: usize = 10;
+
+struct Args {
+    positional: Vec<String>,
+    prefix: Option<String>,
+    prefix_file: Option<String>,
+    layer_snapshots: Option<String>,
+    start: Option<String>,
+    limit: Option<String>,
+    trace: Option<String>,
+    checkpoint_every: Option<String>,
+    checkpoint: Option<String>,
+    resume: Option<String>,
+    packed: bool,
+    profile: bool,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut result = Args {
+            positional: vec![],
+            prefix: None,
+            prefix_file: None,
+            layer_snapshots: None,
+            start: None,
+            limit: None,
+            trace: None,
+            checkpoint_every: None,
+            checkpoint: None,
+            resume: None,
+            packed: false,
+            profile: false,
+        };
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            let slot = match &arg[..] {
+                "--prefix" => &mut result.prefix,
+                "--prefix-file" => &mut result.prefix_file,
+                "--layer-snapshots" => &mut result.layer_snapshots,
+                "--start" => &mut result.start,
+                "--limit" => &mut result.limit,
+                "--trace" => &mut result.trace,
+                "--checkpoint-every" => &mut result.checkpoint_every,
+                "--checkpoint" => &mut result.checkpoint,
+                "--resume" => &mut result.resume,
+                "--packed" => {
+                    result.packed = true;
+                    continue;
+                }
+                "--profile" => {
+                    result.profile = true;
+                    continue;
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ => {
+                    result.positional.push(arg);
+                    continue;
+                }
+            };
+            // The empty prefix is a valid value, so only a missing argument is an error
+            *slot = Some(args.next().ok_or(format!("missing value for {}", arg))?);
+        }
+        Ok(result)
+    }
+
+    fn prefix(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let prefix = match (&self.prefix, &self.prefix_file) {
+            (Some(_), Some(_)) => return Err("--prefix and --prefix-file are exclusive".into()),
+            (Some(prefix), None) => prefix.as_bytes().to_vec(),
+            (None, Some(path)) => fs::read(path)?,
+            (None, None) => DEFAULT_PREFIX.as_bytes().to_vec(),
+        };
+        parse_prefix(&prefix).map_err(|e| format!("invalid prefix: {}", e))?;
+        Ok(prefix)
+    }
+
+    fn number(value: &Option<String>, name: &str) -> Result<Option<usize>, Box<dyn Error>> {
+        match value {
+            Some(n) => Ok(Some(
+                n.parse()
+                    .map_err(|_| format!("{} expects a number", name))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn write_bases(&self, path: &str, bases: &[Base]) -> Result<(), Box<dyn Error>> {
+        let data = if self.packed {
+            packed::encode(bases)
+        } else {
+            to_u8_vec(bases)
+        };
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn layer_snapshots(&self) -> Result<Option<&Path>, Box<dyn Error>> {
+        match &self.layer_snapshots {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                Ok(Some(Path::new(dir)))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    if env::args().len() != 4 {
-        return usage();
+fn run(command: &str, args: &Args) -> Result<bool, Box<dyn Error>> {
+    match (command, &args.positional[..]) {
+        ("execute", [dna, rna]) => {
+            let trace = match &args.trace {
+                Some(path) => Some(TraceWriter::new(io::BufWriter::new(fs::File::create(
+                    path,
+                )?))),
+                None => None,
+            };
+            let checkpoints = match Args::number(&args.checkpoint_every, "--checkpoint-every")? {
+                Some(0) => return Err("--checkpoint-every expects a positive number".into()),
+                Some(every) => {
+                    let path = match &args.checkpoint {
+                        Some(path) => path.clone(),
+                        None => format!("{}.checkpoint", rna),
+                    };
+                    let every = u32::try_from(every).unwrap_or(u32::MAX);
+                    Some(CheckpointWriter::new(every, Path::new(&path)))
+                }
+                None => None,
+            };
+            let (prefix, dna_data) = (args.prefix()?, fs::read(dna)?);
+            let observer = (SlowIterationReporter::default(), trace, checkpoints);
+            // The profiler wraps the other observers to leave their time out
+            let (rna_out, (_, trace, checkpoints)) = if args.profile {
+                let profiler = Profiler::wrapping(PROFILE_TOP_N, observer);
+                let (rna_out, profiler) = execute_with(&prefix, &dna_data, profiler)
+                    .map_err(|e| format!("{}: {}", dna, e))?;
+                print!("{}", profiler.report());
+                (rna_out, profiler.into_inner())
+            } else {
+                execute_with(&prefix, &dna_data, observer).map_err(|e| format!("{}: {}", dna, e))?
+            };
+            if let Some(mut trace) = trace {
+                trace.finish()?;
+            }
+            if let Some(mut checkpoints) = checkpoints {
+                checkpoints.finish()?;
+            }
+            args.write_bases(rna, &rna_out)?;
+        }
+        ("build", [rna, png]) => {
+            let bitmap = build(&fs::read(rna)?, args.layer_snapshots()?)
+                .map_err(|e| format!("{}: {}", rna, e))?;
+            write_bitmap_as_png(&bitmap, fs::File::create(png)?)?;
+        }
+        ("render", [dna, png]) => {
+            let (prefix, dna_data) = (args.prefix()?, fs::read(dna)?);
+            let layer_snapshots = args.layer_snapshots()?;
+            let reporter = SlowIterationReporter::default();
+            let bitmap = render_with(&prefix, &dna_data, layer_snapshots, reporter)
+                .map_err(|e| format!("{}: {}", dna, e))?;
+            write_bitmap_as_png(&bitmap, fs::File::create(png)?)?;
+        }
+        ("score", [png, target]) => {
+            let bitmap = read_png_as_bitmap(fs::File::open(png)?)?;
+            let target = read_png_as_bitmap(fs::File::open(target)?)?;
+            let differing_pixels = count_differing_pixels(&bitmap, &target);
+            let prefix_len = parse_prefix(&args.prefix()?)?.len();
+            println!("Differing pixels: {}", differing_pixels);
+            println!("Prefix length: {}", prefix_len);
+            println!("Risk: {}", risk(differing_pixels, prefix_len));
+        }
+        ("rna", [subcommand, rna_in]) if subcommand == "disasm" => {
+            let rna_in =
+                parse_bases(&fs::read(rna_in)?).map_err(|e| format!("{}: {}", rna_in, e))?;
+            print!("{}", rna::disassemble(&rna_in));
+        }
+        ("rna", [subcommand, text, rna_out]) if subcommand == "asm" => {
+            let rna_out_data = rna::assemble(&fs::read_to_string(text)?)
+                .map_err(|e| format!("{}: {}", text, e))?;
+            args.write_bases(rna_out, &rna_out_data)?;
+        }
+        ("dna", [subcommand, text, prefix]) if subcommand == "asm" => {
+            let prefix_data = dna::assemble(&fs::read_to_string(text)?)
+                .map_err(|e| format!("{}: {}", text, e))?;
+            args.write_bases(prefix, &prefix_data)?;
+        }
+        ("dna", [subcommand, dna_in]) if subcommand == "disasm" => {
+            let state = ExecutionState::new(&args.prefix()?, &fs::read(dna_in)?)
+                .map_err(|e| format!("{}: {}", dna_in, e))?;
+            let start = Args::number(&args.start, "--start")?.unwrap_or(0);
+            let limit = Args::number(&args.limit, "--limit")?;
+            print!("{}", dna::disassemble(&state, start, limit));
+        }
+        ("cli", []) if args.resume.is_some() => {
+            // The checkpoint already holds the DNA with its prefix
+            if args.prefix.is_some() || args.prefix_file.is_some() {
+                return Err("--resume and --prefix or --prefix-file are exclusive".into());
+            }
+            let path = args.resume.as_ref().unwrap();
+            let checkpoint =
+                Checkpoint::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+            cli_resume(checkpoint)?;
+        }
+        ("cli", [dna]) if args.resume.is_none() => {
+            cli_main(&args.prefix()?, &fs::read(dna)?)?;
+        }
+        ("convert", [bases_in, bases_out]) => {
+            let bases =
+                parse_bases(&fs::read(bases_in)?).map_err(|e| format!("{}: {}", bases_in, e))?;
+            args.write_bases(bases_out, &bases)?;
+        }
+        _ => return Ok(false),
     }
-    let command = env::args().nth(1).unwrap();
-    let arg0 = env::args().nth(2).unwrap();
-    let arg1 = env::args().nth(3).unwrap();
-    match &command[..] {
-        "execute" => {
-            let rna = execute(b"IIPIFFCPICICIICPIICIPPPICIIC", &fs::read(arg0)?);
-            fs::write(arg1, to_u8_vec(&rna))?;
-        }
-        "build" => {
-            let bitmap = build(&fs::read(arg0)?);
-            write_bitmap_as_png(&bitmap, fs::File::create(arg1)?)?;
-        }
-        "cli" => {
-            cli_main(arg0.as_bytes(), &fs::read(arg1)?)?;
-        }
-        _ => return usage(),
+    Ok(true)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    let result = Args::parse(args).and_then(|args| run(&command, &args).map_err(|e| e.to_string()));
+    match result {
+        Ok(true) => {}
+        Ok(false) => {
+            usage();
+            process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
     }
-    Ok(())
 }
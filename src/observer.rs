@@ -0,0 +1,518 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::build::{Bitmap, Command, Direction, Pixel, Position};
+use crate::exec::{Pattern, Template};
+use crate::png_utils::write_bitmap_as_png_rgba;
+use crate::types::*;
+
+// Hooks into `ExecutionState::step`. All callbacks do nothing by default, so
+// an observer only implements what it is interested in. Observers are combined
+// with tuples, `()` observes nothing.
+pub trait ExecutionObserver {
+    fn iteration_started(&mut self, _iteration: u32, _dna: &DNA) {}
+    fn pattern_decoded(&mut self, _pattern: &Pattern) {}
+    fn template_decoded(&mut self, _template: &Template) {}
+    // The environment is only valid until the DNA gets replaced
+    fn match_succeeded(&mut self, _length: usize, _env: &[DNASlice], _dna: &DNA) {}
+    fn match_failed(&mut self) {}
+    // Called before the replacement is put in front of the DNA
+    fn replacement_built(&mut self, _replacement: &[DNAChunk]) {}
+    fn rna_emitted(&mut self, _rna: &[Base]) {}
+    fn iteration_finished(&mut self, _iteration: u32, _dna: &DNA, _rna: &[Base]) {}
+}
+
+impl ExecutionObserver for () {}
+
+// Lets optional observers be part of a tuple
+impl<T: ExecutionObserver> ExecutionObserver for Option<T> {
+    fn iteration_started(&mut self, iteration: u32, dna: &DNA) {
+        if let Some(o) = self {
+            o.iteration_started(iteration, dna);
+        }
+    }
+    fn pattern_decoded(&mut self, pattern: &Pattern) {
+        if let Some(o) = self {
+            o.pattern_decoded(pattern);
+        }
+    }
+    fn template_decoded(&mut self, template: &Template) {
+        if let Some(o) = self {
+            o.template_decoded(template);
+        }
+    }
+    fn match_succeeded(&mut self, length: usize, env: &[DNASlice], dna: &DNA) {
+        if let Some(o) = self {
+            o.match_succeeded(length, env, dna);
+        }
+    }
+    fn match_failed(&mut self) {
+        if let Some(o) = self {
+            o.match_failed();
+        }
+    }
+    fn replacement_built(&mut self, replacement: &[DNAChunk]) {
+        if let Some(o) = self {
+            o.replacement_built(replacement);
+        }
+    }
+    fn rna_emitted(&mut self, rna: &[Base]) {
+        if let Some(o) = self {
+            o.rna_emitted(rna);
+        }
+    }
+    fn iteration_finished(&mut self, iteration: u32, dna: &DNA, rna: &[Base]) {
+        if let Some(o) = self {
+            o.iteration_finished(iteration, dna, rna);
+        }
+    }
+}
+
+macro_rules! tuple_observer {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: ExecutionObserver),+> ExecutionObserver for ($($name,)+) {
+            fn iteration_started(&mut self, iteration: u32, dna: &DNA) {
+                $(self.$idx.iteration_started(iteration, dna);)+
+            }
+            fn pattern_decoded(&mut self, pattern: &Pattern) {
+                $(self.$idx.pattern_decoded(pattern);)+
+            }
+            fn template_decoded(&mut self, template: &Template) {
+                $(self.$idx.template_decoded(template);)+
+            }
+            fn match_succeeded(&mut self, length: usize, env: &[DNASlice], dna: &DNA) {
+                $(self.$idx.match_succeeded(length, env, dna);)+
+            }
+            fn match_failed(&mut self) {
+                $(self.$idx.match_failed();)+
+            }
+            fn replacement_built(&mut self, replacement: &[DNAChunk]) {
+                $(self.$idx.replacement_built(replacement);)+
+            }
+            fn rna_emitted(&mut self, rna: &[Base]) {
+                $(self.$idx.rna_emitted(rna);)+
+            }
+            fn iteration_finished(&mut self, iteration: u32, dna: &DNA, rna: &[Base]) {
+                $(self.$idx.iteration_finished(iteration, dna, rna);)+
+            }
+        }
+    };
+}
+
+tuple_observer!(A 0, B 1);
+tuple_observer!(A 0, B 1, C 2);
+tuple_observer!(A 0, B 1, C 2, D 3);
+
+// Hooks into `BuilderState::step`, the counterpart of `ExecutionObserver`
+// for the RNA builder. Layer callbacks get the layers after the change.
+pub trait BuilderObserver {
+    fn command_started(&mut self, _iteration: u32, _command: &Command) {}
+    fn bucket_changed(&mut self, _bucket_size: usize) {}
+    fn moved(&mut self, _from: Position, _to: Position) {}
+    fn turned(&mut self, _from: Direction, _to: Direction) {}
+    fn mark_set(&mut self, _mark: Position) {}
+    fn line_drawn(&mut self, _from: Position, _to: Position, _pixel: Pixel) {}
+    fn fill_performed(&mut self, _pos: Position, _pixel: Pixel) {}
+    fn layer_pushed(&mut self, _iteration: u32, _bitmaps: &[Bitmap]) {}
+    fn layer_composed(&mut self, _iteration: u32, _bitmaps: &[Bitmap]) {}
+    fn layer_clipped(&mut self, _iteration: u32, _bitmaps: &[Bitmap]) {}
+    fn command_executed(&mut self, _iteration: u32, _command: &Command, _bitmaps: &[Bitmap]) {}
+}
+
+impl BuilderObserver for () {}
+
+macro_rules! tuple_builder_observer {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: BuilderObserver),+> BuilderObserver for ($($name,)+) {
+            fn command_started(&mut self, iteration: u32, command: &Command) {
+                $(self.$idx.command_started(iteration, command);)+
+            }
+            fn bucket_changed(&mut self, bucket_size: usize) {
+                $(self.$idx.bucket_changed(bucket_size);)+
+            }
+            fn moved(&mut self, from: Position, to: Position) {
+                $(self.$idx.moved(from, to);)+
+            }
+            fn turned(&mut self, from: Direction, to: Direction) {
+                $(self.$idx.turned(from, to);)+
+            }
+            fn mark_set(&mut self, mark: Position) {
+                $(self.$idx.mark_set(mark);)+
+            }
+            fn line_drawn(&mut self, from: Position, to: Position, pixel: Pixel) {
+                $(self.$idx.line_drawn(from, to, pixel);)+
+            }
+            fn fill_performed(&mut self, pos: Position, pixel: Pixel) {
+                $(self.$idx.fill_performed(pos, pixel);)+
+            }
+            fn layer_pushed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+                $(self.$idx.layer_pushed(iteration, bitmaps);)+
+            }
+            fn layer_composed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+                $(self.$idx.layer_composed(iteration, bitmaps);)+
+            }
+            fn layer_clipped(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+                $(self.$idx.layer_clipped(iteration, bitmaps);)+
+            }
+            fn command_executed(&mut self, iteration: u32, command: &Command, bitmaps: &[Bitmap]) {
+                $(self.$idx.command_executed(iteration, command, bitmaps);)+
+            }
+        }
+    };
+}
+
+tuple_builder_observer!(A 0, B 1);
+tuple_builder_observer!(A 0, B 1, C 2);
+tuple_builder_observer!(A 0, B 1, C 2, D 3);
+
+// Prints every step of the execution while enabled
+#[derive(Clone, Default)]
+pub struct DebugPrinter {
+    pub enabled: bool,
+}
+
+impl ExecutionObserver for DebugPrinter {
+    fn iteration_started(&mut self, iteration: u32, dna: &DNA) {
+        if self.enabled {
+            println!("iteration {}", iteration);
+            println!("dna length: {}", dna.len());
+        }
+    }
+
+    fn pattern_decoded(&mut self, pattern: &Pattern) {
+        if self.enabled {
+            println!("pattern: {}", pattern);
+        }
+    }
+
+    fn template_decoded(&mut self, template: &Template) {
+        if self.enabled {
+            println!("template: {}", template);
+        }
+    }
+
+    fn match_succeeded(&mut self, length: usize, env: &[DNASlice], dna: &DNA) {
+        if self.enabled {
+            println!("match length: {}", length);
+            for (i, p) in env.iter().enumerate() {
+                print!("env[{}] = ", i);
+                for b in dna.render(&p.slice(0..10.min(p.len()))) {
+                    print!("{:?}", b);
+                }
+                println!("{} ({})", if p.len() > 10 { "..." } else { "" }, p.len());
+            }
+        }
+    }
+
+    fn iteration_finished(&mut self, _iteration: u32, _dna: &DNA, rna: &[Base]) {
+        if self.enabled {
+            println!("rna length: {} ({})", rna.len() / 7, rna.len());
+            println!();
+        }
+    }
+}
+
+// Reports iterations taking longer than 10ms along with the DNA layout
+#[derive(Clone, Default)]
+pub struct SlowIterationReporter {
+    started: Option<Instant>,
+}
+
+impl ExecutionObserver for SlowIterationReporter {
+    fn iteration_started(&mut self, _iteration: u32, _dna: &DNA) {
+        self.started = Some(Instant::now());
+    }
+
+    fn iteration_finished(&mut self, iteration: u32, dna: &DNA, _rna: &[Base]) {
+        let elapsed = match self.started.take() {
+            Some(started) => started.elapsed(),
+            None => return,
+        };
+        if elapsed.as_millis() > 10 {
+            println!("SLOW ITERATION {}: {}ms", iteration, elapsed.as_millis());
+            dna.debug_print();
+        }
+    }
+}
+
+// Keeps the groups of the last successful match. The environment does not
+// outlive the iteration, so only the length and the first `limit` bases of
+// each group are copied.
+#[derive(Clone, Default)]
+pub struct LastMatch {
+    limit: usize,
+    iteration: u32,
+    pub matched: Option<(u32, usize)>, // iteration and length of the match
+    pub groups: Vec<(usize, Vec<Base>)>,
+}
+
+impl LastMatch {
+    pub fn new(limit: usize) -> Self {
+        LastMatch {
+            limit,
+            ..LastMatch::default()
+        }
+    }
+}
+
+impl ExecutionObserver for LastMatch {
+    fn iteration_started(&mut self, iteration: u32, _dna: &DNA) {
+        self.iteration = iteration;
+    }
+
+    fn match_succeeded(&mut self, length: usize, env: &[DNASlice], dna: &DNA) {
+        self.matched = Some((self.iteration, length));
+        self.groups = env
+            .iter()
+            .map(|e| (e.len(), dna.bases(e).take(self.limit).collect()))
+            .collect();
+    }
+}
+
+// Prints every RNA command and its effect while enabled
+#[derive(Clone, Default)]
+pub struct BuilderDebugPrinter {
+    pub enabled: bool,
+}
+
+impl BuilderObserver for BuilderDebugPrinter {
+    fn command_started(&mut self, iteration: u32, command: &Command) {
+        if self.enabled {
+            println!("Step {}", iteration);
+            println!("{}", command);
+        }
+    }
+
+    fn moved(&mut self, from: Position, to: Position) {
+        if self.enabled {
+            println!("pos: {} -> {}", from, to);
+        }
+    }
+
+    fn turned(&mut self, from: Direction, to: Direction) {
+        if self.enabled {
+            println!("dir: {:?} -> {:?}", from, to);
+        }
+    }
+
+    fn mark_set(&mut self, mark: Position) {
+        if self.enabled {
+            println!("mark := {}", mark);
+        }
+    }
+
+    fn line_drawn(&mut self, from: Position, to: Position, _pixel: Pixel) {
+        if self.enabled {
+            println!("line: {} -> {}", from, to);
+        }
+    }
+
+    fn fill_performed(&mut self, pos: Position, _pixel: Pixel) {
+        if self.enabled {
+            println!("fill: {}", pos);
+        }
+    }
+}
+
+// Callbacks cannot fail, so observers doing I/O keep their first error here,
+// stop writing and report the error from their `finish`
+#[derive(Debug, Default)]
+pub struct DeferredError(Option<io::Error>);
+
+impl DeferredError {
+    // Runs `f` unless an earlier write failed
+    pub fn run(&mut self, f: impl FnOnce() -> io::Result<()>) {
+        if self.0.is_none() {
+            self.0 = f().err();
+        }
+    }
+
+    pub fn take(&mut self) -> io::Result<()> {
+        match self.0.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+// Saves the top layer as `<dir>/<iteration>.png` before a layer is pushed and
+// after layers are composed or clipped. Does nothing without a directory.
+#[derive(Default)]
+pub struct LayerSnapshots {
+    dir: Option<PathBuf>,
+    error: DeferredError,
+}
+
+impl LayerSnapshots {
+    pub fn new(dir: Option<&Path>) -> Self {
+        LayerSnapshots {
+            dir: dir.map(Path::to_path_buf),
+            error: DeferredError::default(),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.error.take()
+    }
+
+    fn write(&mut self, iteration: u32, bitmap: &Bitmap) {
+        if let Some(dir) = &self.dir {
+            self.error.run(|| {
+                let file = std::fs::File::create(dir.join(format!("{}.png", iteration)))?;
+                write_bitmap_as_png_rgba(bitmap, file)
+            });
+        }
+    }
+}
+
+impl BuilderObserver for LayerSnapshots {
+    fn layer_pushed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+        self.write(iteration, &bitmaps[bitmaps.len() - 2]);
+    }
+
+    fn layer_composed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+        self.write(iteration, bitmaps.last().unwrap());
+    }
+
+    fn layer_clipped(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+        self.write(iteration, bitmaps.last().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::BuilderState;
+    use crate::exec::{example_dna, ExecutionState};
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl ExecutionObserver for Recorder {
+        fn iteration_started(&mut self, iteration: u32, _dna: &DNA) {
+            self.0.push(format!("start {}", iteration));
+        }
+        fn pattern_decoded(&mut self, pattern: &Pattern) {
+            self.0.push(format!("pattern {}", pattern));
+        }
+        fn template_decoded(&mut self, template: &Template) {
+            self.0.push(format!("template {}", template));
+        }
+        fn match_succeeded(&mut self, length: usize, env: &[DNASlice], _dna: &DNA) {
+            self.0.push(format!("match {} {}", length, env.len()));
+        }
+        fn match_failed(&mut self) {
+            self.0.push("no match".to_string());
+        }
+        fn rna_emitted(&mut self, rna: &[Base]) {
+            self.0.push(format!("rna {}", rna.len()));
+        }
+        fn iteration_finished(&mut self, iteration: u32, dna: &DNA, _rna: &[Base]) {
+            self.0.push(format!("finish {} {}", iteration, dna.len()));
+        }
+    }
+
+    #[test]
+    fn test_observer_callbacks() {
+        let dna = example_dna();
+        let observer = (Recorder::default(), DebugPrinter::default());
+        let mut state = ExecutionState::with_observer(b"", &dna, observer).unwrap();
+        while state.step() {}
+        assert_eq!(
+            state.observer().0 .0,
+            vec![
+                "start 0",
+                "rna 7",
+                "pattern (!2)P",
+                "template \\0:0",
+                "match 3 1",
+                "finish 0 9",
+                "start 1",
+                "pattern P",
+                "template ",
+                "no match",
+                "finish 1 1",
+                "start 2",
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct BuilderRecorder(Vec<String>);
+
+    impl BuilderObserver for BuilderRecorder {
+        fn bucket_changed(&mut self, bucket_size: usize) {
+            self.0.push(format!("bucket {}", bucket_size));
+        }
+        fn moved(&mut self, from: Position, to: Position) {
+            self.0.push(format!("move {} {}", from, to));
+        }
+        fn turned(&mut self, from: Direction, to: Direction) {
+            self.0.push(format!("turn {:?} {:?}", from, to));
+        }
+        fn line_drawn(&mut self, from: Position, to: Position, pixel: Pixel) {
+            self.0.push(format!("line {} {} {}", from, to, pixel.rgb.0));
+        }
+        fn layer_pushed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+            self.0.push(format!("push {} {}", iteration, bitmaps.len()));
+        }
+        fn layer_composed(&mut self, iteration: u32, bitmaps: &[Bitmap]) {
+            self.0
+                .push(format!("compose {} {}", iteration, bitmaps.len()));
+        }
+        fn command_executed(&mut self, iteration: u32, command: &Command, _bitmaps: &[Bitmap]) {
+            self.0.push(format!("{} {}", iteration, command));
+        }
+    }
+
+    #[test]
+    fn test_deferred_error() {
+        let mut error = DeferredError::default();
+        error.run(|| Ok(()));
+        error.run(|| Err(io::Error::other("first")));
+        error.run(|| panic!("runs after an error"));
+        assert_eq!(error.take().unwrap_err().to_string(), "first");
+        assert!(error.take().is_ok());
+    }
+
+    #[test]
+    fn test_last_match() {
+        let dna = example_dna();
+        let mut state = ExecutionState::with_observer(b"", &dna, LastMatch::new(1)).unwrap();
+        while state.step() {}
+        assert_eq!(state.observer().matched, Some((0, 3)));
+        assert_eq!(state.observer().groups, vec![(2, vec![I])]);
+    }
+
+    #[test]
+    fn test_builder_observer_callbacks() {
+        let rna =
+            crate::rna::assemble("+RED\nCW\nMOVE\nLINE\nLAYER\nCOMPOSE\nCLEAR\nCLEAR").unwrap();
+        let observer = (BuilderRecorder::default(), BuilderDebugPrinter::default());
+        let mut builder = BuilderState::with_observer(&rna, observer).unwrap();
+        for _ in 0..builder.commands.len() {
+            builder.step();
+        }
+        assert_eq!(
+            builder.observer().0 .0,
+            vec![
+                "bucket 1",
+                "0 +RED",
+                "turn Right Down",
+                "1 CW",
+                "move (0, 0) (0, 1)",
+                "2 MOVE",
+                "line (0, 1) (0, 0) 255",
+                "3 LINE",
+                "push 4 2",
+                "4 LAYER",
+                "compose 5 1",
+                "5 COMPOSE",
+                "bucket 0",
+                "6 CLEAR",
+                "7 CLEAR",
+            ]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::error::{Error, Result};
+use crate::types::*;
+
+// Bases packed four to a byte, the first base in the lowest two bits
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedBases {
+    data: Vec<u8>,
+    len: usize,
+}
+
+fn code(b: Base) -> u8 {
+    match b {
+        I => 0,
+        C => 1,
+        F => 2,
+        P => 3,
+    }
+}
+
+fn base(code: u8) -> Base {
+    match code & 3 {
+        0 => I,
+        1 => C,
+        2 => F,
+        _ => P,
+    }
+}
+
+impl PackedBases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(bases: usize) -> Self {
+        PackedBases {
+            data: Vec::with_capacity(bases.div_ceil(4)),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Base {
+        assert!(idx < self.len, "index {} out of {} bases", idx, self.len);
+        base(self.data[idx / 4] >> (idx % 4 * 2))
+    }
+
+    pub fn push(&mut self, b: Base) {
+        if self.len.is_multiple_of(4) {
+            self.data.push(0);
+        }
+        self.data[self.len / 4] |= code(b) << (self.len % 4 * 2);
+        self.len += 1;
+    }
+
+    pub fn extend(&mut self, bases: &[Base]) {
+        self.data.reserve(bases.len().div_ceil(4));
+        for &b in bases {
+            self.push(b);
+        }
+    }
+
+    // Copies whole bytes when both sides are aligned on a byte boundary
+    pub fn extend_from_packed(&mut self, other: &PackedBases, range: Range<usize>) {
+        let mut start = range.start;
+        if self.len.is_multiple_of(4) && start.is_multiple_of(4) {
+            let bytes = (range.end - start) / 4;
+            self.data.extend(&other.data[start / 4..start / 4 + bytes]);
+            self.len += bytes * 4;
+            start += bytes * 4;
+        }
+        for idx in start..range.end {
+            self.push(other.get(idx));
+        }
+    }
+
+    pub fn iter(&self, range: Range<usize>) -> impl Iterator<Item = Base> + '_ {
+        range.map(move |idx| self.get(idx))
+    }
+
+    pub fn to_vec(&self) -> Vec<Base> {
+        self.iter(0..self.len).collect()
+    }
+}
+
+impl From<&[Base]> for PackedBases {
+    fn from(bases: &[Base]) -> Self {
+        let mut result = PackedBases::with_capacity(bases.len());
+        result.extend(bases);
+        result
+    }
+}
+
+// The packed file format is the magic, the number of bases as a little-endian
+// u64 and then the packed bases. A text file never starts with a zero byte.
+pub const MAGIC: &[u8; 4] = b"\0ICP";
+const HEADER_SIZE: usize = 12;
+
+pub fn is_packed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encode(bases: &[Base]) -> Vec<u8> {
+    let packed = PackedBases::from(bases);
+    let mut result = Vec::with_capacity(HEADER_SIZE + packed.data.len());
+    result.extend(MAGIC);
+    result.extend(&(bases.len() as u64).to_le_bytes());
+    result.extend(&packed.data);
+    result
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<Base>> {
+    let truncated = Error::TruncatedPacked { length: data.len() };
+    if !is_packed(data) || data.len() < HEADER_SIZE {
+        return Err(truncated);
+    }
+    let mut count = [0; 8];
+    count.copy_from_slice(&data[4..HEADER_SIZE]);
+    let count = u64::from_le_bytes(count);
+    let body = &data[HEADER_SIZE..];
+    if count > body.len() as u64 * 4 {
+        return Err(truncated);
+    }
+    let count = usize::try_from(count).map_err(|_| truncated)?;
+    let mut result = Vec::with_capacity(count);
+    for idx in 0..count {
+        result.push(base(body[idx / 4] >> (idx % 4 * 2)));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_bases() {
+        let bases = to_base_vec(b"ICFPPFCIICCFFPPI").unwrap();
+        let packed = PackedBases::from(&bases[..]);
+        assert_eq!(packed.len(), bases.len());
+        assert_eq!(packed.to_vec(), bases);
+        for start in 0..bases.len() {
+            for end in start..=bases.len() {
+                let mut copy = PackedBases::from(&bases[..start % 3]);
+                copy.extend_from_packed(&packed, start..end);
+                let mut expected = bases[..start % 3].to_vec();
+                expected.extend(&bases[start..end]);
+                assert_eq!(copy.to_vec(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_format() {
+        let bases = to_base_vec(b"PICFCFP").unwrap();
+        let data = encode(&bases);
+        assert_eq!(data.len(), HEADER_SIZE + 2);
+        assert_eq!(decode(&data).unwrap(), bases);
+        assert_eq!(parse_bases(&data).unwrap(), bases);
+        assert!(matches!(
+            decode(&data[..HEADER_SIZE + 1]),
+            Err(Error::TruncatedPacked { length: 13 })
+        ));
+        assert!(matches!(
+            decode(&data[..6]),
+            Err(Error::TruncatedPacked { length: 6 })
+        ));
+    }
+}
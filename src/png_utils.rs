@@ -1,21 +1,19 @@
-use std::io::{BufWriter, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 
 use crate::build::{Bitmap, Pixel, Position, RGB};
 
-use png;
-
 pub fn write_bitmap_as_png<W: Write>(bitmap: &Bitmap, out: W) -> std::io::Result<()> {
     let writer = BufWriter::new(out);
     
-    let mut encoder = png::Encoder::new(writer, 600, 600);
+    let mut encoder = png::Encoder::new(writer, bitmap.width(), bitmap.height());
     encoder.set_color(png::ColorType::RGB);
     encoder.set_depth(png::BitDepth::Eight);
     let mut writer = encoder.write_header()?;
 
-    let mut data = vec![0u8; 3 * 600 * 600];
+    let mut data = vec![0u8; 3 * (bitmap.width() * bitmap.height()) as usize];
     let mut i = 0;
-    for y in 0..600 {
-        for x in 0..600 {
+    for y in 0..bitmap.height() {
+        for x in 0..bitmap.width() {
             let Pixel { rgb: RGB(r, g, b), a: _ } = bitmap.get(Position(x, y));
             data[i] = r;
             data[i + 1] = g;
@@ -28,19 +26,18 @@ pub fn write_bitmap_as_png<W: Write>(bitmap: &Bitmap, out: W) -> std::io::Result
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn write_bitmap_as_png_rgba<W: Write>(bitmap: &Bitmap, out: W) -> std::io::Result<()> {
     let writer = BufWriter::new(out);
     
-    let mut encoder = png::Encoder::new(writer, 600, 600);
+    let mut encoder = png::Encoder::new(writer, bitmap.width(), bitmap.height());
     encoder.set_color(png::ColorType::RGBA);
     encoder.set_depth(png::BitDepth::Eight);
     let mut writer = encoder.write_header()?;
 
-    let mut data = vec![0u8; 4 * 600 * 600];
+    let mut data = vec![0u8; 4 * (bitmap.width() * bitmap.height()) as usize];
     let mut i = 0;
-    for y in 0..600 {
-        for x in 0..600 {
+    for y in 0..bitmap.height() {
+        for x in 0..bitmap.width() {
             let Pixel { rgb: RGB(r, g, b), a } = bitmap.get(Position(x, y));
             data[i] = r;
             data[i + 1] = g;
@@ -53,3 +50,53 @@ pub fn write_bitmap_as_png_rgba<W: Write>(bitmap: &Bitmap, out: W) -> std::io::R
 
     Ok(())
 }
+
+pub fn read_png_as_bitmap<R: Read>(input: R) -> io::Result<Bitmap> {
+    let decoder = png::Decoder::new(BufReader::new(input));
+    let (info, mut reader) = decoder.read_info()?;
+    let mut data = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    // The default transformations expand palettes and strip 16-bit samples,
+    // so only the channel layout is left to handle
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected indexed color after expansion",
+            ))
+        }
+    };
+    let mut bitmap = Bitmap::transparent_with_size(info.width, info.height);
+    for y in 0..info.height {
+        let row = &data[y as usize * info.line_size..];
+        for x in 0..info.width {
+            let p = &row[x as usize * channels..(x as usize + 1) * channels];
+            let pixel = match *p {
+                [l] => Pixel {
+                    rgb: RGB(l, l, l),
+                    a: 255,
+                },
+                [l, a] => Pixel {
+                    rgb: RGB(l, l, l),
+                    a,
+                },
+                [r, g, b] => Pixel {
+                    rgb: RGB(r, g, b),
+                    a: 255,
+                },
+                [r, g, b, a] => Pixel {
+                    rgb: RGB(r, g, b),
+                    a,
+                },
+                _ => unreachable!(),
+            };
+            bitmap.set(Position(x, y), pixel);
+        }
+    }
+    Ok(bitmap)
+}
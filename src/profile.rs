@@ -0,0 +1,254 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::exec::{Pattern, Template};
+use crate::observer::ExecutionObserver;
+use crate::types::*;
+
+pub const PHASES: [&str; 5] = [
+    "pattern decode",
+    "template decode",
+    "matching",
+    "replacement",
+    "consolidation",
+];
+
+// Iteration times are bucketed by powers of ten, from below 1us to 1s and up
+const HISTOGRAM_BUCKETS: [&str; 8] = [
+    "< 1us", "< 10us", "< 100us", "< 1ms", "< 10ms", "< 100ms", "< 1s", ">= 1s",
+];
+
+#[derive(Debug, Clone)]
+pub struct SlowIteration {
+    pub iteration: u32,
+    pub elapsed: Duration,
+    pub phases: [Duration; 5],
+    pub pattern: String,
+}
+
+// Times every phase of every iteration from the observer callbacks. The
+// replacement phase ends when the replacement is built, the rest of the
+// iteration is spent putting it into the DNA storage. Other observers are
+// wrapped by the profiler rather than put next to it in a tuple, so that the
+// time spent in them and in the profiler itself is left out of the phases.
+pub struct Profiler<O: ExecutionObserver = ()> {
+    inner: O,
+    top_n: usize,
+    last: Instant,
+    current: [Duration; 5],
+    pattern: Option<Pattern>,
+    pub iterations: u64,
+    pub total: Duration,
+    pub phases: [Duration; 5],
+    pub histogram: [u64; 8],
+    // Sorted from the slowest, at most `top_n` long
+    pub slowest: Vec<SlowIteration>,
+}
+
+impl Profiler {
+    pub fn new(top_n: usize) -> Self {
+        Self::wrapping(top_n, ())
+    }
+}
+
+impl<O: ExecutionObserver> Profiler<O> {
+    pub fn wrapping(top_n: usize, inner: O) -> Self {
+        Profiler {
+            inner,
+            top_n,
+            last: Instant::now(),
+            current: Default::default(),
+            pattern: None,
+            iterations: 0,
+            total: Duration::default(),
+            phases: Default::default(),
+            histogram: Default::default(),
+            slowest: vec![],
+        }
+    }
+
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> O {
+        self.inner
+    }
+
+    fn lap(&mut self, phase: usize) {
+        let now = Instant::now();
+        self.current[phase] += now - self.last;
+        self.last = now;
+    }
+
+    // Leaves the time spent in `f` out of the current phase
+    fn excluding(&mut self, f: impl FnOnce(&mut Self)) {
+        let start = Instant::now();
+        f(self);
+        self.last += start.elapsed();
+    }
+
+    pub fn report(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut result = String::new();
+        writeln!(
+            result,
+            "Iterations: {}, total {:.3} ms",
+            self.iterations,
+            ms(self.total)
+        )
+        .unwrap();
+        writeln!(result).unwrap();
+        writeln!(result, "{:<16} {:>12} {:>6}", "Phase", "Total ms", "Share").unwrap();
+        for (name, &time) in PHASES.iter().zip(&self.phases) {
+            let share = if self.total.as_nanos() == 0 {
+                0.0
+            } else {
+                time.as_secs_f64() / self.total.as_secs_f64() * 100.0
+            };
+            writeln!(result, "{:<16} {:>12.3} {:>5.1}%", name, ms(time), share).unwrap();
+        }
+        writeln!(result).unwrap();
+        writeln!(result, "{:<16} {:>12}", "Iteration time", "Count").unwrap();
+        for (name, count) in HISTOGRAM_BUCKETS.iter().zip(&self.histogram) {
+            writeln!(result, "{:<16} {:>12}", name, count).unwrap();
+        }
+        writeln!(result).unwrap();
+        writeln!(
+            result,
+            "{:>9} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}  Pattern",
+            "Iteration", "Total ms", "Pattern", "Template", "Match", "Replace", "Consol."
+        )
+        .unwrap();
+        for s in &self.slowest {
+            write!(result, "{:>9} {:>10.3}", s.iteration, ms(s.elapsed)).unwrap();
+            for &time in &s.phases {
+                write!(result, " {:>10.3}", ms(time)).unwrap();
+            }
+            writeln!(result, "  {}", s.pattern).unwrap();
+        }
+        result
+    }
+}
+
+fn histogram_bucket(elapsed: Duration) -> usize {
+    let mut bucket = 0;
+    let mut limit = 1000; // ns
+    while bucket < HISTOGRAM_BUCKETS.len() - 1 && elapsed.as_nanos() >= limit {
+        bucket += 1;
+        limit *= 10;
+    }
+    bucket
+}
+
+// Long search constants would make the report unreadable
+fn shorten(s: String) -> String {
+    const MAX_LEN: usize = 60;
+    if s.chars().count() <= MAX_LEN {
+        s
+    } else {
+        s.chars().take(MAX_LEN).collect::<String>() + "..."
+    }
+}
+
+impl<O: ExecutionObserver> ExecutionObserver for Profiler<O> {
+    fn iteration_started(&mut self, iteration: u32, dna: &DNA) {
+        self.inner.iteration_started(iteration, dna);
+        self.last = Instant::now();
+        self.current = Default::default();
+    }
+
+    fn pattern_decoded(&mut self, pattern: &Pattern) {
+        self.lap(0);
+        self.excluding(|p| {
+            p.pattern = Some(pattern.clone());
+            p.inner.pattern_decoded(pattern);
+        });
+    }
+
+    fn template_decoded(&mut self, template: &Template) {
+        self.lap(1);
+        self.excluding(|p| p.inner.template_decoded(template));
+    }
+
+    fn match_succeeded(&mut self, length: usize, env: &[DNASlice], dna: &DNA) {
+        self.lap(2);
+        self.excluding(|p| p.inner.match_succeeded(length, env, dna));
+    }
+
+    fn match_failed(&mut self) {
+        self.lap(2);
+        self.excluding(|p| p.inner.match_failed());
+    }
+
+    fn replacement_built(&mut self, replacement: &[DNAChunk]) {
+        self.lap(3);
+        self.excluding(|p| p.inner.replacement_built(replacement));
+    }
+
+    fn rna_emitted(&mut self, rna: &[Base]) {
+        self.excluding(|p| p.inner.rna_emitted(rna));
+    }
+
+    fn iteration_finished(&mut self, iteration: u32, dna: &DNA, rna: &[Base]) {
+        self.lap(4);
+        let elapsed = self.current.iter().sum::<Duration>();
+        self.iterations += 1;
+        self.total += elapsed;
+        for (total, &time) in self.phases.iter_mut().zip(&self.current) {
+            *total += time;
+        }
+        self.histogram[histogram_bucket(elapsed)] += 1;
+        let is_slow = self.slowest.len() < self.top_n
+            || self.slowest.last().is_some_and(|s| s.elapsed < elapsed);
+        if is_slow && self.top_n > 0 {
+            let pattern = self.pattern.take().map_or(String::new(), |p| p.to_string());
+            let idx = self.slowest.partition_point(|s| s.elapsed >= elapsed);
+            self.slowest.insert(
+                idx,
+                SlowIteration {
+                    iteration,
+                    elapsed,
+                    phases: self.current,
+                    pattern: shorten(pattern),
+                },
+            );
+            self.slowest.truncate(self.top_n);
+        }
+        self.inner.iteration_finished(iteration, dna, rna);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{example_dna, execute_with};
+    use crate::observer::LastMatch;
+
+    #[test]
+    fn test_profiler() {
+        let dna = example_dna();
+        let (_, profiler) = execute_with(b"", &dna, Profiler::new(1)).unwrap();
+        assert_eq!(profiler.iterations, 2);
+        assert_eq!(profiler.histogram.iter().sum::<u64>(), 2);
+        assert_eq!(profiler.phases.iter().sum::<Duration>(), profiler.total);
+        assert_eq!(profiler.slowest.len(), 1);
+        let report = profiler.report();
+        assert!(report.starts_with("Iterations: 2, total "));
+        assert!(report.contains("\nconsolidation "));
+        assert!(report.contains(&format!("  {}\n", profiler.slowest[0].pattern)));
+
+        let profiler = Profiler::wrapping(1, LastMatch::new(1));
+        let (_, profiler) = execute_with(b"", &dna, profiler).unwrap();
+        assert_eq!(profiler.iterations, 2);
+        assert_eq!(profiler.into_inner().matched, Some((0, 3)));
+    }
+
+    #[test]
+    fn test_histogram_bucket() {
+        assert_eq!(histogram_bucket(Duration::from_nanos(999)), 0);
+        assert_eq!(histogram_bucket(Duration::from_micros(1)), 1);
+        assert_eq!(histogram_bucket(Duration::from_micros(150)), 3);
+        assert_eq!(histogram_bucket(Duration::from_secs(5)), 7);
+    }
+}
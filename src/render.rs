@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use crate::build::{Bitmap, BuilderState};
+use crate::error::Result;
+use crate::exec::ExecutionState;
+use crate::observer::{ExecutionObserver, LayerSnapshots};
+
+// Runs the DNA and feeds the RNA to the builder as soon as it is produced,
+// so the whole RNA never has to be kept around or written out
+pub fn render(prefix: &[u8], dna: &[u8], layer_snapshots: Option<&Path>) -> Result<Bitmap> {
+    render_with(prefix, dna, layer_snapshots, ())
+}
+
+// Like `render`, with an observer on the execution
+pub fn render_with<O: ExecutionObserver>(
+    prefix: &[u8],
+    dna: &[u8],
+    layer_snapshots: Option<&Path>,
+    observer: O,
+) -> Result<Bitmap> {
+    let mut exec_state = ExecutionState::with_observer(prefix, dna, observer)?;
+    let mut build_state = BuilderState::with_observer(&[], LayerSnapshots::new(layer_snapshots))?;
+    loop {
+        let running = exec_state.step();
+        // Only whole commands are handed over, the rest waits for the next step
+        let complete = exec_state.rna.len() / 7 * 7;
+        if complete > 0 {
+            build_state.extend(&exec_state.rna[..complete]);
+            exec_state.rna.drain(..complete);
+            while (build_state.iteration as usize) < build_state.commands.len() {
+                build_state.step();
+            }
+        }
+        if !running {
+            break;
+        }
+    }
+    exec_state.check_finish()?;
+    build_state.observer_mut().finish()?;
+    Ok(build_state.bitmaps.pop().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{build, Position};
+    use crate::exec::execute;
+    use crate::types::to_u8_vec;
+
+    #[test]
+    fn test_render_matches_execute_and_build() {
+        // Emits +RED and FILL from the pattern, then matches and replaces nothing
+        let dna = b"IIIPIPIIIPIIIPIIPIIPIICIIC";
+        let rna = execute(b"", dna).unwrap();
+        let expected = build(&to_u8_vec(&rna), None).unwrap();
+        let actual = render(b"", dna, None).unwrap();
+        for &p in &[Position(0, 0), Position(300, 300), Position(599, 599)] {
+            assert_eq!(actual.get(p), expected.get(p));
+        }
+        assert_eq!(actual.get(Position(0, 0)).a, 255);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::Write;
+
+use crate::build::Command;
+use crate::error::{Error, Result};
+use crate::types::*;
+
+// One command per line, prefixed with its offset in the RNA. An incomplete
+// trailing command is kept as `UNKNOWN` so that nothing gets lost.
+pub fn disassemble(rna: &[Base]) -> String {
+    let mut result = String::new();
+    for (i, chunk) in rna.chunks(7).enumerate() {
+        writeln!(result, "{:>8}  {}", i * 7, Command::decode(chunk)).unwrap();
+    }
+    result
+}
+
+// Reads the `disassemble` output back. The offsets are optional and ignored,
+// blank lines and `#` comments are skipped. An `UNKNOWN` command shorter than
+// 7 bases would shift every command after it, so it may only come last.
+pub fn assemble(text: &str) -> Result<Vec<Base>> {
+    let mut result = vec![];
+    let mut partial = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let line = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax = |message| Error::Syntax {
+            line: i + 1,
+            message,
+        };
+        if let Some(line) = partial {
+            return Err(syntax(format!(
+                "a partial command on line {} must be the last one",
+                line
+            )));
+        }
+        if let Some(command) = Command::parse(line) {
+            result.extend(command.encode());
+            continue;
+        }
+        match partial_command(line) {
+            Some(bases) => {
+                result.extend(bases);
+                partial = Some(i + 1);
+            }
+            None => return Err(syntax(format!("unknown RNA command: {}", line))),
+        }
+    }
+    Ok(result)
+}
+
+fn partial_command(line: &str) -> Option<Vec<Base>> {
+    match line.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+        ["UNKNOWN", bases] => to_base_vec(bases.as_bytes())
+            .ok()
+            .filter(|b| !b.is_empty() && b.len() < 7),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_assemble() {
+        let rna = to_base_vec(b"PIPIIIPPIIPIIPCCCCCCCPIPIIICPIP").unwrap();
+        let text = disassemble(&rna);
+        assert_eq!(
+            text,
+            "       0  +RED
+       7  FILL
+      14  UNKNOWN CCCCCCC
+      21  +BLACK
+      28  UNKNOWN PIP
+"
+        );
+        assert_eq!(assemble(&text).unwrap(), rna);
+        assert_eq!(
+            assemble("# header\nMOVE # step\n\nCW\n").unwrap(),
+            to_base_vec(b"PIIIIIPPFFFFFP").unwrap()
+        );
+        assert!(matches!(
+            assemble("MOVE\nJUMP\n"),
+            Err(Error::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            assemble("UNKNOWN PIP\n+RED\n"),
+            Err(Error::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            assemble("UNKNOWN PIPIIIPP\n"),
+            Err(Error::Syntax { line: 1, .. })
+        ));
+    }
+}
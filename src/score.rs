@@ -0,0 +1,47 @@
+use crate::build::{Bitmap, Position};
+
+// Only the colors end up in the final picture, so alpha is not compared.
+// Pixels covered by just one of the bitmaps always count as different.
+pub fn count_differing_pixels(bitmap: &Bitmap, target: &Bitmap) -> usize {
+    let width = bitmap.width().min(target.width());
+    let height = bitmap.height().min(target.height());
+    let mut count = (bitmap.width() * bitmap.height() + target.width() * target.height()
+        - 2 * width * height) as usize;
+    for y in 0..height {
+        for x in 0..width {
+            if bitmap.get(Position(x, y)).rgb != target.get(Position(x, y)).rgb {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+// Same weighting as the contest: every wrong pixel costs ten bases of prefix
+pub fn risk(differing_pixels: usize, prefix_len: usize) -> usize {
+    10 * differing_pixels + prefix_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{Pixel, RGB};
+    use crate::png_utils::{read_png_as_bitmap, write_bitmap_as_png};
+
+    #[test]
+    fn test_score_roundtrip() {
+        let mut bitmap = Bitmap::transparent();
+        let red = Pixel {
+            rgb: RGB(255, 0, 0),
+            a: 255,
+        };
+        bitmap.draw_line(Position(0, 0), Position(9, 0), red);
+
+        let mut png = vec![];
+        write_bitmap_as_png(&bitmap, &mut png).unwrap();
+        let target = read_png_as_bitmap(&png[..]).unwrap();
+        assert_eq!(count_differing_pixels(&bitmap, &target), 0);
+        assert_eq!(count_differing_pixels(&Bitmap::transparent(), &target), 10);
+        assert_eq!(risk(10, 28), 128);
+    }
+}
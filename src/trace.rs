@@ -0,0 +1,166 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::exec::{Pattern, Template};
+use crate::observer::{DeferredError, ExecutionObserver};
+use crate::types::*;
+
+// What happened in one iteration of the DNA execution
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceRecord {
+    pub iteration: u32,
+    pub dna_len_before: usize,
+    pub dna_len_after: usize,
+    pub pattern: String,
+    pub template: String,
+    pub match_length: Option<usize>, // None if the match failed
+    pub env_sizes: Vec<usize>,
+    pub rna: Vec<Base>,
+    pub elapsed: Duration,
+}
+
+impl TraceRecord {
+    // One JSON object per line, so runs can be grepped and loaded offline
+    pub fn to_json(&self) -> String {
+        let mut result = String::new();
+        write!(
+            result,
+            "{{\"iteration\":{},\"dna_len_before\":{},\"dna_len_after\":{}",
+            self.iteration, self.dna_len_before, self.dna_len_after
+        )
+        .unwrap();
+        write!(
+            result,
+            ",\"pattern\":{},\"template\":{}",
+            json_string(&self.pattern),
+            json_string(&self.template)
+        )
+        .unwrap();
+        match self.match_length {
+            Some(length) => write!(result, ",\"match_length\":{}", length).unwrap(),
+            None => result.push_str(",\"match_length\":null"),
+        }
+        let env_sizes = self
+            .env_sizes
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        write!(result, ",\"env_sizes\":[{}]", env_sizes.join(",")).unwrap();
+        let rna = self.rna.iter().map(|b| b.to_string()).collect::<String>();
+        write!(
+            result,
+            ",\"rna\":{},\"elapsed_us\":{}}}",
+            json_string(&rna),
+            self.elapsed.as_micros()
+        )
+        .unwrap();
+        result
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(result, "\\u{:04x}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// Writes a record for every finished iteration
+pub struct TraceWriter<W: Write> {
+    out: W,
+    record: TraceRecord,
+    started: Option<Instant>,
+    error: DeferredError,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        TraceWriter {
+            out,
+            record: TraceRecord::default(),
+            started: None,
+            error: DeferredError::default(),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.error.take()?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> ExecutionObserver for TraceWriter<W> {
+    fn iteration_started(&mut self, iteration: u32, dna: &DNA) {
+        self.record = TraceRecord {
+            iteration,
+            dna_len_before: dna.len(),
+            ..TraceRecord::default()
+        };
+        self.started = Some(Instant::now());
+    }
+
+    fn pattern_decoded(&mut self, pattern: &Pattern) {
+        self.record.pattern = pattern.to_string();
+    }
+
+    fn template_decoded(&mut self, template: &Template) {
+        self.record.template = template.to_string();
+    }
+
+    fn match_succeeded(&mut self, length: usize, env: &[DNASlice], _dna: &DNA) {
+        self.record.match_length = Some(length);
+        self.record.env_sizes = env.iter().map(DNASlice::len).collect();
+    }
+
+    fn rna_emitted(&mut self, rna: &[Base]) {
+        self.record.rna.extend(rna);
+    }
+
+    fn iteration_finished(&mut self, _iteration: u32, dna: &DNA, _rna: &[Base]) {
+        self.record.dna_len_after = dna.len();
+        self.record.elapsed = self
+            .started
+            .take()
+            .map_or(Duration::default(), |s| s.elapsed());
+        let (out, record) = (&mut self.out, &self.record);
+        self.error.run(|| writeln!(out, "{}", record.to_json()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{example_dna, execute, execute_with};
+
+    #[test]
+    fn test_trace_writer() {
+        let dna = example_dna();
+        let (rna, mut trace) = execute_with(b"", &dna, TraceWriter::new(vec![])).unwrap();
+        assert_eq!(rna, execute(b"", &dna).unwrap());
+        trace.finish().unwrap();
+        let trace = String::from_utf8(trace.out).unwrap();
+        let lines = trace.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(
+            "{\"iteration\":0,\"dna_len_before\":43,\"dna_len_after\":9,\"pattern\":\"(!2)P\",\"template\":\"\\\\0:0\",\"match_length\":3,\"env_sizes\":[2],\"rna\":\"PIPIIIP\",\"elapsed_us\":"
+        ));
+        assert!(lines[1].starts_with(
+            "{\"iteration\":1,\"dna_len_before\":9,\"dna_len_after\":1,\"pattern\":\"P\",\"template\":\"\",\"match_length\":null,\"env_sizes\":[],\"rna\":\"\","
+        ));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("?\"IC\"\\0\n"), "\"?\\\"IC\\\"\\\\0\\n\"");
+    }
+}
@@ -1,6 +1,9 @@
-use std::collections::VecDeque;
 use std::iter::{IntoIterator, Iterator};
-use std::ops::{Index, Range};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::error::{Error, Result};
+use crate::packed::{self, PackedBases};
 
 pub use Base::*;
 
@@ -24,22 +27,57 @@ impl std::fmt::Display for Base {
     }
 }
 
-// Panics if `data` contains an invalid base
-pub fn to_base_vec(data: &[u8]) -> Vec<Base> {
-    data.into_iter()
-        .map(|c| match c {
-            b'I' => I,
-            b'C' => C,
-            b'F' => F,
-            b'P' => P,
-            _ => panic!(),
+pub fn to_base_vec(data: &[u8]) -> Result<Vec<Base>> {
+    data.iter()
+        .enumerate()
+        .map(|(offset, &byte)| match byte {
+            b'I' => Ok(I),
+            b'C' => Ok(C),
+            b'F' => Ok(F),
+            b'P' => Ok(P),
+            _ => Err(Error::InvalidBase { offset, byte }),
         })
         .collect()
 }
 
+// Like `to_base_vec`, but skips whitespace so hand-edited files load fine.
+// Files in the packed format are detected by their magic.
+pub fn parse_bases(data: &[u8]) -> Result<Vec<Base>> {
+    parse_bases_impl(data, false)
+}
+
+// Prefix files may also contain `#` comments running to the end of the line
+pub fn parse_prefix(data: &[u8]) -> Result<Vec<Base>> {
+    parse_bases_impl(data, true)
+}
+
+fn parse_bases_impl(data: &[u8], allow_comments: bool) -> Result<Vec<Base>> {
+    if packed::is_packed(data) {
+        return packed::decode(data);
+    }
+    let mut result = Vec::with_capacity(data.len());
+    let mut in_comment = false;
+    for (offset, &byte) in data.iter().enumerate() {
+        if in_comment {
+            in_comment = byte != b'\n';
+            continue;
+        }
+        match byte {
+            b'I' => result.push(I),
+            b'C' => result.push(C),
+            b'F' => result.push(F),
+            b'P' => result.push(P),
+            b'#' if allow_comments => in_comment = true,
+            _ if byte.is_ascii_whitespace() => {}
+            _ => return Err(Error::InvalidBase { offset, byte }),
+        }
+    }
+    Ok(result)
+}
+
 pub fn to_u8_vec(bases: &[Base]) -> Vec<u8> {
     bases
-        .into_iter()
+        .iter()
         .map(|b| match b {
             I => b'I',
             C => b'C',
@@ -49,100 +87,294 @@ pub fn to_u8_vec(bases: &[Base]) -> Vec<u8> {
         .collect()
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+// A rope leaf. The chunk lives as long as some leaf refers to it, so slices
+// taken from the DNA stay valid whatever happens to the DNA later.
+#[derive(Debug, Clone)]
 struct DNAStorageSlice {
-    pub idx: usize,    // storage index
+    pub chunk: Rc<PackedBases>,
     pub start: usize,  // start within the chunk
     pub length: usize, // length of the slice
 }
 
+// The part of a leaf that falls inside a `DNASlice`
+struct Part<'a> {
+    chunk: &'a PackedBases,
+    start: usize,
+    length: usize,
+}
+
+impl<'a> Part<'a> {
+    fn bases(&self) -> impl Iterator<Item = Base> + 'a {
+        self.chunk.iter(self.start..(self.start + self.length))
+    }
+}
+
+// Persistent AVL-balanced rope over storage slices. Nodes are shared between
+// the DNA and the slices taken from it, so slicing and concatenation never
+// copy more than O(log n) nodes.
+#[derive(Debug)]
+enum RopeNode {
+    Leaf(DNAStorageSlice),
+    Concat {
+        left: Rc<RopeNode>,
+        right: Rc<RopeNode>,
+        length: usize,
+        height: usize,
+    },
+}
+
+impl RopeNode {
+    fn len(&self) -> usize {
+        match self {
+            RopeNode::Leaf(s) => s.length,
+            RopeNode::Concat { length, .. } => *length,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            RopeNode::Leaf(_) => 0,
+            RopeNode::Concat { height, .. } => *height,
+        }
+    }
+
+    fn children(&self) -> (&Rc<RopeNode>, &Rc<RopeNode>) {
+        match self {
+            RopeNode::Leaf(_) => unreachable!("a leaf has no children"),
+            RopeNode::Concat { left, right, .. } => (left, right),
+        }
+    }
+
+    fn leaf(s: DNAStorageSlice) -> Rc<Self> {
+        Rc::new(RopeNode::Leaf(s))
+    }
+
+    // Links two subtrees whose heights differ by at most one
+    fn node(left: Rc<Self>, right: Rc<Self>) -> Rc<Self> {
+        Rc::new(RopeNode::Concat {
+            length: left.len() + right.len(),
+            height: left.height().max(right.height()) + 1,
+            left,
+            right,
+        })
+    }
+
+    // Links two subtrees whose heights differ by at most two
+    fn balance(left: Rc<Self>, right: Rc<Self>) -> Rc<Self> {
+        if left.height() > right.height() + 1 {
+            let (a, b) = left.children();
+            if a.height() >= b.height() {
+                Self::node(a.clone(), Self::node(b.clone(), right))
+            } else {
+                let (b1, b2) = b.children();
+                Self::node(
+                    Self::node(a.clone(), b1.clone()),
+                    Self::node(b2.clone(), right),
+                )
+            }
+        } else if right.height() > left.height() + 1 {
+            let (a, b) = right.children();
+            if b.height() >= a.height() {
+                Self::node(Self::node(left, a.clone()), b.clone())
+            } else {
+                let (a1, a2) = a.children();
+                Self::node(
+                    Self::node(left, a1.clone()),
+                    Self::node(a2.clone(), b.clone()),
+                )
+            }
+        } else {
+            Self::node(left, right)
+        }
+    }
+
+    // O(|height(left) - height(right)|)
+    fn concat(left: Rc<Self>, right: Rc<Self>) -> Rc<Self> {
+        if left.height() > right.height() + 1 {
+            let (a, b) = left.children();
+            let b = Self::concat(b.clone(), right);
+            Self::balance(a.clone(), b)
+        } else if right.height() > left.height() + 1 {
+            let (a, b) = right.children();
+            let a = Self::concat(left, a.clone());
+            Self::balance(a, b.clone())
+        } else {
+            Self::node(left, right)
+        }
+    }
+
+    fn concat_opt(left: Option<Rc<Self>>, right: Option<Rc<Self>>) -> Option<Rc<Self>> {
+        match (left, right) {
+            (Some(left), Some(right)) => Some(Self::concat(left, right)),
+            (left, None) => left,
+            (None, right) => right,
+        }
+    }
+
+    // Splits into the first `idx` bases and the rest, O(log n)
+    fn split(node: &Rc<Self>, idx: usize) -> (Option<Rc<Self>>, Option<Rc<Self>>) {
+        if idx == 0 {
+            return (None, Some(node.clone()));
+        }
+        if idx >= node.len() {
+            return (Some(node.clone()), None);
+        }
+        match &**node {
+            RopeNode::Leaf(s) => (
+                Some(Self::leaf(DNAStorageSlice {
+                    chunk: s.chunk.clone(),
+                    start: s.start,
+                    length: idx,
+                })),
+                Some(Self::leaf(DNAStorageSlice {
+                    chunk: s.chunk.clone(),
+                    start: s.start + idx,
+                    length: s.length - idx,
+                })),
+            ),
+            RopeNode::Concat { left, right, .. } => {
+                if idx <= left.len() {
+                    let (ll, lr) = Self::split(left, idx);
+                    (ll, Self::concat_opt(lr, Some(right.clone())))
+                } else {
+                    let (rl, rr) = Self::split(right, idx - left.len());
+                    (Self::concat_opt(Some(left.clone()), rl), rr)
+                }
+            }
+        }
+    }
+
+    // Returns the chunk and the position within it
+    fn locate(mut node: &Self, mut idx: usize) -> (&Rc<PackedBases>, usize) {
+        loop {
+            match node {
+                RopeNode::Leaf(s) => return (&s.chunk, s.start + idx),
+                RopeNode::Concat { left, right, .. } => {
+                    if idx < left.len() {
+                        node = left;
+                    } else {
+                        idx -= left.len();
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A view of `length` bases of a rope starting at `offset`. Narrowing the view
+// is O(1), the rope itself is only cut when it gets concatenated.
 #[derive(Debug, Clone)]
 pub struct DNASlice {
-    // The slices are stored in reverse as the DNA is modified from the front
-    parts: Vec<DNAStorageSlice>,
-    total_len: usize,
+    root: Option<Rc<RopeNode>>,
+    offset: usize,
+    length: usize,
 }
 
 impl DNASlice {
     pub fn len(&self) -> usize {
-        self.total_len
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
     }
 
     pub fn slice(&self, range: Range<usize>) -> DNASlice {
-        let mut parts = vec![];
-        let mut start = range.start;
-        let mut length = range.end - range.start;
-        for slice in self.parts.iter().rev() {
-            if start >= slice.length {
-                start -= slice.length;
-            } else {
-                let slice_length = (slice.length - start).min(length);
-                parts.push(DNAStorageSlice {
-                    idx: slice.idx,
-                    start: slice.start + start,
-                    length: slice_length,
-                });
-                start = 0;
-                length -= slice_length;
-                if length == 0 {
-                    break;
-                }
-            }
-        }
+        let end = range.end.min(self.length);
+        let start = range.start.min(end);
         DNASlice {
-            parts: parts.into_iter().rev().collect(),
-            total_len: range.end - range.start,
+            root: self.root.clone(),
+            offset: self.offset + start,
+            length: end - start,
         }
     }
 
-    fn new() -> Self {
+    fn from_root(root: Option<Rc<RopeNode>>) -> Self {
+        let length = root.as_ref().map_or(0, |r| r.len());
         DNASlice {
-            parts: vec![],
-            total_len: 0,
+            root,
+            offset: 0,
+            length,
         }
     }
 
-    fn push_front(&mut self, s: DNAStorageSlice) {
-        if s.length == 0 {
-            return;
+    // The rope cut down to exactly the bases of this slice
+    fn trimmed_root(&self) -> Option<Rc<RopeNode>> {
+        let root = self.root.as_ref()?;
+        if self.length == 0 {
+            return None;
         }
-        let length = s.length;
-        self.parts.push(s);
-        self.total_len += length;
+        let (_, rest) = RopeNode::split(root, self.offset);
+        let (result, _) = RopeNode::split(&rest?, self.length);
+        result
     }
 
-    fn pop_front(&mut self) -> Option<(usize, usize)> {
-        if self.parts.is_empty() {
-            return None;
+    fn parts(&self) -> Parts<'_> {
+        Parts {
+            stack: self.root.iter().map(|r| &**r).collect(),
+            skip: self.offset,
+            remaining: self.length,
         }
-        let last_idx = self.parts.len() - 1;
-        let slice = &mut self.parts[last_idx];
-        let (storage_idx, idx) = (slice.idx, slice.start);
-        if slice.length > 1 {
-            slice.start += 1;
-            slice.length -= 1;
-        } else {
-            self.parts.pop().unwrap();
+    }
+
+    fn get(&self, idx: usize) -> Option<Base> {
+        if idx >= self.length {
+            return None;
         }
-        self.total_len -= 1;
-        Some((storage_idx, idx))
+        let (chunk, idx) = RopeNode::locate(self.root.as_ref()?, self.offset + idx);
+        Some(chunk.get(idx))
     }
 
-    fn truncate_front(&mut self, mut count: usize) {
-        while count > 0 {
-            let last_idx = self.parts.len() - 1;
-            let slice = &mut self.parts[last_idx];
-            if slice.length <= count {
-                let slice = self.parts.pop().unwrap();
-                count -= slice.length;
-                self.total_len -= slice.length;
-            } else {
-                slice.start += count;
-                slice.length -= count;
-                self.total_len -= count;
-                break;
+    fn pop_front(&mut self) -> Option<Base> {
+        let result = self.get(0)?;
+        self.truncate_front(1);
+        Some(result)
+    }
+
+    fn truncate_front(&mut self, count: usize) {
+        let count = count.min(self.length);
+        self.offset += count;
+        self.length -= count;
+    }
+}
+
+// Iterates over the parts of a `DNASlice` in order
+struct Parts<'a> {
+    stack: Vec<&'a RopeNode>,
+    skip: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Parts<'a> {
+    type Item = Part<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            match self.stack.pop()? {
+                RopeNode::Leaf(s) => {
+                    let length = (s.length - self.skip).min(self.remaining);
+                    let part = Part {
+                        chunk: &s.chunk,
+                        start: s.start + self.skip,
+                        length,
+                    };
+                    self.skip = 0;
+                    self.remaining -= length;
+                    return Some(part);
+                }
+                RopeNode::Concat { left, right, .. } => {
+                    if self.skip >= left.len() {
+                        self.skip -= left.len();
+                        self.stack.push(right);
+                    } else {
+                        self.stack.push(right);
+                        self.stack.push(left);
+                    }
+                }
             }
         }
+        None
     }
 }
 
@@ -151,45 +383,31 @@ pub enum DNAChunk {
     Slice(DNASlice),
 }
 
+#[derive(Clone)]
 pub struct DNA {
-    dna_storage: Vec<Vec<Base>>,
     dna: DNASlice,
 }
 
 const CONSOLIDATION_TARGET_SIZE: usize = 4 * 1024; // 4KiB
 
-impl Index<usize> for DNA {
-    type Output = Base;
-
-    fn index(&self, mut idx: usize) -> &Self::Output {
-        for slice in self.dna.parts.iter().rev() {
-            if idx >= slice.length {
-                idx -= slice.length;
-            } else {
-                return &self.dna_storage[slice.idx][slice.start + idx];
-            }
-        }
-        panic!("Out of bounds");
-    }
-}
-
 impl DNA {
     pub fn new(data: &[Vec<Base>]) -> Self {
-        let mut storage_chunk = vec![];
+        let mut storage_chunk = PackedBases::new();
         for vec in data {
             storage_chunk.extend(vec);
         }
         let length = storage_chunk.len();
+        let root = if length == 0 {
+            None
+        } else {
+            Some(RopeNode::leaf(DNAStorageSlice {
+                chunk: Rc::new(storage_chunk),
+                start: 0,
+                length,
+            }))
+        };
         DNA {
-            dna_storage: vec![storage_chunk],
-            dna: DNASlice {
-                parts: vec![DNAStorageSlice {
-                    idx: 0,
-                    start: 0,
-                    length,
-                }],
-                total_len: length,
-            },
+            dna: DNASlice::from_root(root),
         }
     }
 
@@ -197,6 +415,10 @@ impl DNA {
         self.dna.len()
     }
 
+    pub fn get(&self, idx: usize) -> Option<Base> {
+        self.dna.get(idx)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.dna.len() == 0
     }
@@ -205,91 +427,100 @@ impl DNA {
         self.dna.slice(range)
     }
 
-    pub fn window(&self, start: usize, window_size: usize) -> DNASlidingWindow {
-        if start + window_size > self.dna.total_len {
-            panic!("Invalid index/window size");
+    // Returns the position of the first occurrence of `needle` at or after
+    // `start`. Knuth-Morris-Pratt over the storage slices, so every base is
+    // looked at once, O(n + m).
+    pub fn find(&self, needle: &[Base], start: usize) -> Option<usize> {
+        if start > self.dna.len() {
+            return None;
+        }
+        if needle.is_empty() {
+            return Some(start);
         }
-        DNASlidingWindow::new(&self, start, window_size)
+        // failure[k] is the length of the longest proper border of needle[..=k]
+        let mut failure = vec![0; needle.len()];
+        let mut k = 0;
+        for j in 1..needle.len() {
+            while k > 0 && needle[j] != needle[k] {
+                k = failure[k - 1];
+            }
+            if needle[j] == needle[k] {
+                k += 1;
+            }
+            failure[j] = k;
+        }
+        let mut matched = 0;
+        let mut pos = start;
+        for p in self.dna.slice(start..self.dna.len()).parts() {
+            for b in p.bases() {
+                while matched > 0 && b != needle[matched] {
+                    matched = failure[matched - 1];
+                }
+                if b == needle[matched] {
+                    matched += 1;
+                }
+                pos += 1;
+                if matched == needle.len() {
+                    return Some(pos - needle.len());
+                }
+            }
+        }
+        None
     }
 
     pub fn render(&self, slice: &DNASlice) -> Vec<Base> {
-        let mut result = vec![];
-        for p in slice.parts.iter().rev() {
-            result.extend(&self.dna_storage[p.idx][p.start..(p.start + p.length)]);
+        let mut result = Vec::with_capacity(slice.len());
+        for p in slice.parts() {
+            result.extend(p.bases());
+        }
+        result
+    }
+
+    pub fn bases<'a>(&'a self, slice: &'a DNASlice) -> impl Iterator<Item = Base> + 'a {
+        slice.parts().flat_map(|p| p.bases())
+    }
+
+    fn render_packed(&self, slice: &DNASlice) -> PackedBases {
+        let mut result = PackedBases::with_capacity(slice.len());
+        for p in slice.parts() {
+            result.extend_from_packed(p.chunk, p.start..(p.start + p.length));
         }
         result
     }
 
     pub fn extend_front(&mut self, data: Vec<DNAChunk>) {
-        let mut current_slices = vec![];
+        let mut root = self.dna.trimmed_root();
         for c in data.into_iter().rev() {
-            match c {
-                DNAChunk::Owned(d) => {
-                    if !current_slices.is_empty() {
-                        self.extend_front_slices(current_slices);
-                        current_slices = vec![];
-                    }
-                    self.extend_front_owned(d);
-                }
-                DNAChunk::Slice(s) => {
-                    current_slices.push(s);
-                }
-            }
-        }
-        if !current_slices.is_empty() {
-            self.extend_front_slices(current_slices);
+            let chunk = match c {
+                DNAChunk::Owned(d) => self.store(PackedBases::from(&d[..])),
+                DNAChunk::Slice(s) => self.consolidate(s),
+            };
+            root = RopeNode::concat_opt(chunk, root);
         }
+        // Cutting the rope down to the view lets go of the chunks only the
+        // bases in front of it referred to
+        self.dna = DNASlice::from_root(root);
     }
 
-    fn extend_front_owned(&mut self, data: Vec<Base>) {
+    fn store(&mut self, data: PackedBases) -> Option<Rc<RopeNode>> {
         if data.is_empty() {
-            return;
+            return None;
         }
-        let length = data.len();
-        self.dna_storage.push(data);
-        self.dna.parts.push(DNAStorageSlice {
-            idx: self.dna_storage.len() - 1,
+        Some(RopeNode::leaf(DNAStorageSlice {
             start: 0,
-            length,
-        });
-        self.dna.total_len += length;
+            length: data.len(),
+            chunk: Rc::new(data),
+        }))
     }
 
-    fn extend_front_slices(&mut self, slices: Vec<DNASlice>) {
-        // This method will consolidate small slices as new chunks
-        if slices.len() == 1 {
-            let slice = &slices[0];
-            if let &[single_part] = &slice.parts[..] {
-                self.dna.push_front(single_part);
-                return;
-            }
-        }
-        let mut new_slice = DNASlice::new();
-        for s in slices {
-            for p in s.parts {
-                if new_slice.total_len + p.length > CONSOLIDATION_TARGET_SIZE {
-                    if new_slice.total_len != 0 {
-                        self.extend_from_slice(new_slice);
-                        new_slice = DNASlice::new();
-                    }
-                }
-                if p.length > CONSOLIDATION_TARGET_SIZE {
-                    self.dna.push_front(p);
-                } else {
-                    new_slice.push_front(p);
-                }
-            }
-        }
-        if new_slice.total_len != 0 {
-            self.extend_from_slice(new_slice);
-        }
-    }
-
-    fn extend_from_slice(&mut self, slice: DNASlice) {
-        if slice.parts.len() == 1 {
-            self.dna.push_front(slice.parts[0]);
+    fn consolidate(&mut self, slice: DNASlice) -> Option<Rc<RopeNode>> {
+        // Small slices made of several parts are copied into a new chunk
+        // to keep the rope from fragmenting into tiny leaves
+        if slice.len() <= CONSOLIDATION_TARGET_SIZE && slice.parts().nth(1).is_some() {
+            let data = self.render_packed(&slice);
+            self.store(data)
         } else {
-            self.extend_front_owned(self.render(&slice));
+            slice.trimmed_root()
         }
     }
 
@@ -298,18 +529,18 @@ impl DNA {
     }
 
     pub fn pop_front(&mut self) -> Option<Base> {
-        let (storage_idx, idx) = self.dna.pop_front()?;
-        Some(self.dna_storage[storage_idx][idx])
+        self.dna.pop_front()
     }
 
     pub fn debug_print(&self) {
-        println!("Total slices: {}", self.dna.parts.len());
-        if !self.dna.parts.is_empty() {
-            let mut min = self.dna.parts[0].length;
+        let parts = self.dna.parts().collect::<Vec<_>>();
+        println!("Total slices: {}", parts.len());
+        if !parts.is_empty() {
+            let mut min = parts[0].length;
             let mut max = min;
             let mut avg = 0;
             let mut used_indices = std::collections::HashSet::new();
-            for p in &self.dna.parts {
+            for p in &parts {
                 if p.length < min {
                     min = p.length;
                 }
@@ -317,57 +548,197 @@ impl DNA {
                     max = p.length;
                 }
                 avg += p.length;
-                used_indices.insert(p.idx);
+                used_indices.insert(p.chunk as *const PackedBases);
             }
-            avg /= self.dna.parts.len();
+            avg /= parts.len();
             println!("min: {}, max: {}, avg: {}", min, max, avg);
             println!("Total used chunks: {}", used_indices.len());
         }
-        println!("Total chunks: {}", self.dna_storage.len());
+        println!(
+            "Rope height: {}",
+            self.dna.root.as_ref().map_or(0, |r| r.height())
+        );
     }
 }
 
-pub struct DNASlidingWindow<'a> {
-    dna: &'a DNA,
-    window: VecDeque<Base>,
-    slice: DNASlice,
-    offset: usize,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bases() {
+        assert_eq!(parse_bases(b"IC FP\r\n").unwrap(), vec![I, C, F, P]);
+        assert_eq!(
+            parse_prefix(b"# header\nIIP # group\r\nIC\n").unwrap(),
+            vec![I, I, P, I, C]
+        );
+        assert!(matches!(
+            parse_bases(b"IC\n# no comments here"),
+            Err(Error::InvalidBase {
+                offset: 3,
+                byte: b'#'
+            })
+        ));
+        assert!(matches!(
+            parse_prefix(b"IC\tx"),
+            Err(Error::InvalidBase {
+                offset: 3,
+                byte: b'x'
+            })
+        ));
+    }
 
-impl<'a> DNASlidingWindow<'a> {
-    fn new(dna: &'a DNA, start: usize, window_size: usize) -> Self {
-        let mut slice = dna.dna.clone();
-        slice.truncate_front(start);
-        let mut window = VecDeque::with_capacity(window_size);
-        for _ in 0..window_size {
-            let (storage_idx, idx) = slice.pop_front().unwrap();
-            window.push_back(dna.dna_storage[storage_idx][idx]);
-        }
-        DNASlidingWindow {
-            dna,
-            window,
-            slice,
-            offset: 0,
+    #[test]
+    fn test_chunk_lifetime() {
+        let mut dna = DNA::new(&[to_base_vec(b"ICFP").unwrap()]);
+        let old = dna.slice(0..4);
+        let (chunk, _) = RopeNode::locate(dna.dna.root.as_ref().unwrap(), 0);
+        let first = Rc::downgrade(chunk);
+        let mut model = to_base_vec(b"ICFP").unwrap();
+        for i in 0..3000 {
+            let owned = vec![[I, C, F, P][i % 4]; 1 + i % 3];
+            let count = (2 + i % 2).min(model.len());
+            dna.truncate_front(count);
+            model.drain(..count);
+            model.splice(0..0, owned.iter().cloned());
+            let rest = dna.slice(0..dna.len());
+            dna.truncate_front(dna.len());
+            dna.extend_front(vec![DNAChunk::Owned(owned), DNAChunk::Slice(rest)]);
         }
+        assert_eq!(dna.render(&dna.slice(0..dna.len())), model);
+        // The slice keeps its bases after the DNA has moved on
+        assert_eq!(dna.render(&old), to_base_vec(b"ICFP").unwrap());
+        assert!(first.upgrade().is_some());
+        drop(old);
+        assert!(first.upgrade().is_none());
     }
 
-    pub fn offset(&self) -> usize {
-        self.offset
+    #[test]
+    fn test_find() {
+        let mut dna = DNA::new(&[to_base_vec(b"CFPICFPICFFP").unwrap()]);
+        // Spread the bases over several storage chunks
+        let rest = dna.slice(4..dna.len());
+        dna.truncate_front(dna.len());
+        dna.extend_front(vec![
+            DNAChunk::Owned(vec![I, C]),
+            DNAChunk::Owned(vec![F]),
+            DNAChunk::Slice(rest),
+        ]);
+        let bases = dna.render(&dna.slice(0..dna.len()));
+        assert_eq!(bases, to_base_vec(b"ICFCFPICFFP").unwrap());
+        let naive = |needle: &[Base], start: usize| {
+            (start..=bases.len()).find(|&i| bases[i..].starts_with(needle))
+        };
+        for needle in &[
+            &b"ICF"[..],
+            b"CFF",
+            b"FP",
+            b"CFCFP",
+            b"ICFCFPICFFP",
+            b"PP",
+            b"P",
+        ] {
+            let needle = to_base_vec(needle).unwrap();
+            for start in 0..bases.len() {
+                assert_eq!(dna.find(&needle, start), naive(&needle, start));
+            }
+        }
+        assert_eq!(dna.find(&[], 3), Some(3));
+        assert_eq!(dna.find(&[I], 12), None);
     }
 
-    pub fn next(&mut self) -> bool {
-        if let Some((storage_idx, idx)) = self.slice.pop_front() {
-            self.window.pop_front();
-            self.window
-                .push_back(self.dna.dna_storage[storage_idx][idx]);
-            self.offset += 1;
-            true
-        } else {
-            false
+    fn check_balanced(node: &RopeNode) -> usize {
+        match node {
+            RopeNode::Leaf(s) => {
+                assert!(s.length > 0);
+                0
+            }
+            RopeNode::Concat {
+                left,
+                right,
+                length,
+                height,
+            } => {
+                let (hl, hr) = (check_balanced(left), check_balanced(right));
+                assert!(hl.max(hr) - hl.min(hr) <= 1);
+                assert_eq!(*height, hl.max(hr) + 1);
+                assert_eq!(*length, left.len() + right.len());
+                *height
+            }
         }
     }
 
-    pub fn is_match(&self, data: &[Base]) -> bool {
-        self.window == data
+    #[test]
+    fn test_rope() {
+        let mut model = to_base_vec(b"ICFPICFPIICCFFPP").unwrap();
+        let mut dna = DNA::new(&[model.clone()]);
+        let mut seed = 12345u32;
+        let mut random = |n: usize| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+        for _ in 0..500 {
+            // Rebuild the front from pieces of itself, like a replacement does
+            let count = random(model.len() + 1);
+            let mut chunks = vec![];
+            let mut expected = vec![];
+            for _ in 0..random(4) {
+                let start = random(model.len() + 1);
+                let end = start + random(model.len() - start + 1);
+                chunks.push(DNAChunk::Slice(dna.slice(start..end)));
+                expected.extend(&model[start..end]);
+                let owned = vec![[I, C, F, P][random(4)]; random(3)];
+                expected.extend(&owned);
+                chunks.push(DNAChunk::Owned(owned));
+            }
+            dna.truncate_front(count);
+            dna.extend_front(chunks);
+            expected.extend(&model[count..]);
+            model = expected;
+            if model.len() > 5000 {
+                dna.truncate_front(model.len() - 100);
+                model.drain(..model.len() - 100);
+            }
+
+            assert_eq!(dna.len(), model.len());
+            assert_eq!(dna.render(&dna.slice(0..dna.len())), model);
+            if let Some(root) = &dna.dna.root {
+                check_balanced(root);
+            }
+            for _ in 0..10 {
+                let idx = random(model.len() + 1);
+                assert_eq!(dna.get(idx), model.get(idx).copied());
+            }
+        }
+        while let Some(b) = dna.pop_front() {
+            assert_eq!(b, model.remove(0));
+        }
+        assert!(model.is_empty());
+
+        // Insertions at random positions, one leaf per chunk
+        let mut root = None;
+        let mut order = vec![];
+        for _ in 0..2000 {
+            let pos = random(order.len() + 1);
+            let (left, right) = match &root {
+                Some(root) => RopeNode::split(root, pos),
+                None => (None, None),
+            };
+            let chunk = Rc::new(PackedBases::from(&[I][..]));
+            let leaf = RopeNode::leaf(DNAStorageSlice {
+                chunk: chunk.clone(),
+                start: 0,
+                length: 1,
+            });
+            root = RopeNode::concat_opt(RopeNode::concat_opt(left, Some(leaf)), right);
+            order.insert(pos, chunk);
+        }
+        let root = root.unwrap();
+        assert!(check_balanced(&root) <= 16);
+        for (i, chunk) in order.iter().enumerate() {
+            let (found, start) = RopeNode::locate(&root, i);
+            assert!(Rc::ptr_eq(found, chunk));
+            assert_eq!(start, 0);
+        }
     }
 }